@@ -1,6 +1,7 @@
 use std::path::PathBuf;
 
 use clap::{Args, Parser as ArgParser, Subcommand};
+use expandr_driver::Escaping;
 
 #[derive(ArgParser)]
 #[command(version, about, long_about = None)]
@@ -46,4 +47,35 @@ pub struct ExpansionArgs {
     /// Emit the lexer logs to FILE
     #[arg(long, value_name = "FILE")]
     pub log_symbols: Option<PathBuf>,
+
+    /// Escape values interpolated into templates (html, html-attr, json, c, shell, python, url).
+    /// An `autoescape` directive in the file takes precedence
+    #[arg(long, value_name = "MODE", value_parser = parse_escaping)]
+    pub autoescape: Option<Escaping>,
+
+    /// Indent all lines of interpolated multi-line values to the column they are interpolated at
+    #[arg(long, default_value_t = false)]
+    pub reindent: bool,
+
+    /// Maximum nesting of mapping applications before expansion fails
+    #[arg(long, value_name = "DEPTH")]
+    pub max_depth: Option<usize>,
+
+    /// Cache the results of all mapping applications by their arguments. Without it, only the
+    /// mappings named in `memoize` directives are cached
+    #[arg(long, default_value_t = false)]
+    pub memoize: bool,
+
+    /// Print statistics like memo cache hits to stderr
+    #[arg(long, default_value_t = false)]
+    pub stats: bool,
+
+    /// Write the output while it is expanded. An existing output file is only replaced if the
+    /// expansion succeeds
+    #[arg(long, default_value_t = false)]
+    pub stream: bool,
+}
+
+fn parse_escaping(mode: &str) -> Result<Escaping, String> {
+    Escaping::from_name(mode).ok_or_else(|| format!("Unknown escaping mode `{mode}`"))
 }
@@ -1,7 +1,12 @@
-use std::{fs, io, path::PathBuf};
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
 
+use anyhow::Context as _;
 use clap::Parser as _;
-use expandr_driver::{build, ModuleRegistry};
+use expandr_driver::{build, BuildOptions, ModuleRegistry};
 
 use crate::cli::{Cli, CliSubCommand, ExpansionArgs};
 
@@ -15,14 +20,19 @@ fn main() {
     let _ = fs::remove_file("logs");
 
     match cli.command {
-        CliSubCommand::Expand(build_args) => expand(build_args),
+        CliSubCommand::Expand(build_args) => {
+            if let Err(e) = expand(build_args) {
+                anstream::eprintln!("Expansion failed:\n{e:#}");
+                std::process::exit(1);
+            }
+        }
         CliSubCommand::Check { .. } => {
             todo!()
         }
     }
 }
 
-fn expand(cli_args: ExpansionArgs) {
+fn expand(cli_args: ExpansionArgs) -> anyhow::Result<()> {
     let (source_name, source) = match cli_args.input_files.as_deref() {
         Some([filename]) => (
             filename.clone(),
@@ -51,31 +61,76 @@ fn expand(cli_args: ExpansionArgs) {
         .as_ref()
         .or(cli_args.all.then_some(&default_tok_logfile));
 
-    let mut output: Box<dyn io::Write> = match cli_args.output.as_ref() {
+    let (mut output, partial_output): (Box<dyn io::Write>, _) = match &cli_args.output {
         Some(path) => {
-            let file = fs::File::create(path).expect("Could not create output file");
-            Box::new(file)
+            let (file, partial_output) = create_output(path, cli_args.stream)?;
+            (Box::new(file), partial_output)
         }
         None => {
             // We generally want to lock stdout for performance if writing a lot
-            Box::new(io::stdout().lock())
+            (Box::new(io::stdout().lock()), None)
         }
     };
 
     let mut module_registry = ModuleRegistry::new();
 
+    let options = BuildOptions {
+        ast_logfile: ast_logfile.cloned(),
+        ctx_logfile: ctx_logfile.cloned(),
+        token_logfile: tok_logfile.cloned(),
+        autoescape: cli_args.autoescape,
+        reindent: cli_args.reindent,
+        max_depth: cli_args.max_depth,
+        memoize: cli_args.memoize,
+        stats: cli_args.stats,
+        stream: cli_args.stream,
+    };
+
     let result = build(
         source_name,
         source,
         &mut output,
         &mut module_registry,
-        ast_logfile,
-        ctx_logfile,
-        tok_logfile.cloned(),
+        &options,
     );
 
+    drop(output);
+
     if let Err(e) = result {
-        anstream::eprintln!("Expansion failed:\n{e:#}");
-        std::process::exit(1);
+        if let Some(partial_output) = &partial_output {
+            let _ = fs::remove_file(partial_output);
+        }
+        return Err(e);
+    }
+
+    if let (Some(partial_output), Some(path)) = (&partial_output, &cli_args.output) {
+        fs::rename(partial_output, path)
+            .with_context(|| format!("Could not move the output into place at {path:?}"))?;
     }
+    Ok(())
+}
+
+/// Create the output file. A streamed expansion can fail after writing parts of the output, so
+/// it is written to a hidden sibling of `path` first, which is moved into place once it succeeded.
+/// Outputs that aren't regular files, like `/dev/stdout` or pipes, are written directly, as are
+/// outputs in directories where no sibling can be created.
+fn create_output(path: &Path, stream: bool) -> anyhow::Result<(fs::File, Option<PathBuf>)> {
+    let is_regular = fs::metadata(path).map_or(true, |meta| meta.is_file());
+    if stream && is_regular {
+        let partial_output = partial_output_path(path);
+        if let Ok(file) = fs::File::create(&partial_output) {
+            return Ok((file, Some(partial_output)));
+        }
+    }
+    let file = fs::File::create(path)
+        .with_context(|| format!("Could not create the output file {path:?}"))?;
+    Ok((file, None))
+}
+
+/// Hidden sibling of `path`, so it is on the same file system and can be renamed
+fn partial_output_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".partial");
+    path.with_file_name(name)
 }
@@ -1,4 +1,4 @@
-use crate::{build, ModuleRegistry};
+use crate::{build, create_output, BuildOptions, ModuleRegistry};
 
 use std::{
     fs,
@@ -33,9 +33,7 @@ fn integration_exr_files() {
             contents,
             &mut out_writer,
             &mut registry,
-            None,
-            None,
-            None,
+            &BuildOptions::default(),
         ) {
             anstream::eprintln!("{e:#}")
         }
@@ -46,3 +44,65 @@ fn integration_exr_files() {
         assert_eq!(actual, expected, "Mismatch in test {filename:?}");
     }
 }
+
+#[test]
+fn streamed_exr_files() {
+    let test_dir = Path::new("tests");
+
+    for entry in fs::read_dir(test_dir).unwrap() {
+        let entry = entry.unwrap();
+        let path = entry.path();
+
+        if path.extension().and_then(|e| e.to_str()) != Some("exr") {
+            continue;
+        }
+
+        let filename = path.file_name().unwrap();
+        let stem = path.file_stem().unwrap().to_string_lossy();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let expected_path: PathBuf = format!("tests/expected/{stem}.out").into();
+
+        let mut output = Vec::new();
+        let mut registry = ModuleRegistry::new();
+
+        if let Err(e) = build(
+            filename.into(),
+            contents,
+            &mut output,
+            &mut registry,
+            &BuildOptions {
+                stream: true,
+                ..BuildOptions::default()
+            },
+        ) {
+            anstream::eprintln!("{e:#}")
+        }
+
+        let expected = fs::read_to_string(&expected_path).unwrap();
+        let actual = String::from_utf8(output).unwrap();
+
+        assert_eq!(actual, expected, "Mismatch in streamed test {filename:?}");
+    }
+}
+
+#[test]
+fn streamed_output_targets() {
+    let dir = std::env::temp_dir().join(format!("expandr-output-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("out.txt");
+
+    // Regular files are replaced once the expansion succeeded
+    let (_, partial_output) = create_output(&path, true).unwrap();
+    assert_eq!(partial_output, Some(dir.join(".out.txt.partial")));
+    let (_, partial_output) = create_output(&path, false).unwrap();
+    assert_eq!(partial_output, None);
+
+    // Everything else is written directly
+    if cfg!(unix) {
+        let (_, partial_output) = create_output(Path::new("/dev/null"), true).unwrap();
+        assert_eq!(partial_output, None);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,78 @@
+//! Run with `cargo bench -p expandr-driver`
+
+use std::{io, path::PathBuf};
+
+use expandr_driver::{build, BuildOptions, ModuleRegistry};
+
+fn main() {
+    divan::main();
+}
+
+/// Python classes with `attributes` properties each. Mostly applies mappings with large
+/// translations, without deep recursion.
+fn generator(classes: usize, attributes: usize) -> String {
+    let mut source = String::from(
+        r#"
+map attribute [class] [i] => '''
+    @property
+    def attribute_[i](self) -> str:
+        """Attribute [i] of [str/pascal class]"""
+        return self._attributes.get("attribute_[i]", "")
+'''
+
+map class [name] [body] => '''
+class [str/pascal name]:
+    def __init__(self, attributes):
+        self._attributes = attributes
+[body]
+'''
+"#,
+    );
+    for class in 0..classes {
+        let name = format!("'generated_class_{class}'");
+        source.push_str(&format!("[class {name} [..\n"));
+        for i in 0..attributes {
+            source.push_str(&format!("    [attribute {name} {i}]\n"));
+        }
+        source.push_str("..]]\n");
+    }
+    source
+}
+
+fn expand(source: String) {
+    build(
+        PathBuf::from("bench.exr"),
+        source,
+        &mut io::sink(),
+        &mut ModuleRegistry::new(),
+        &BuildOptions::default(),
+    )
+    .expect("Benchmark source should expand");
+}
+
+#[divan::bench(args = [10, 100, 1000], sample_count = 20)]
+fn classes(bencher: divan::Bencher, attributes: usize) {
+    bencher
+        .with_inputs(|| generator(10, attributes))
+        .bench_values(expand);
+}
+
+/// `overloads` overloads of `tag` that differ in a leading literal, applied 1000 times
+fn overloads_generator(overloads: usize) -> String {
+    let mut source = String::new();
+    for i in 0..overloads {
+        source.push_str(&format!("map tag t{i} [body] => '<t{i}>[body]</t{i}>'\n"));
+    }
+    source.push_str("map tag [name] [body] => '<[name]>[body]</[name]>'\n");
+    for i in 0..1000 {
+        source.push_str(&format!("[tag t{} 'text']\n", i % overloads));
+    }
+    source
+}
+
+#[divan::bench(args = [10, 100, 1000], sample_count = 20)]
+fn overloads(bencher: divan::Bencher, overloads: usize) {
+    bencher
+        .with_inputs(|| overloads_generator(overloads))
+        .bench_values(expand);
+}
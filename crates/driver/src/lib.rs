@@ -13,22 +13,54 @@ use std::{
 };
 
 use expandr_semantic::{
-    context::{get_owned_context, merge_contexts},
+    context::{
+        get_owned_context, merge_contexts, ExpansionOptions, MemoContext, OptionsContext,
+        DEFAULT_MAX_DEPTH,
+    },
     expand::{Expandable as _, Expanded},
+    memo::MemoCache,
+    ESCAPING_MODES,
 };
 use expandr_syntax::{ast::PathIdentRoot, source_type::Owned, ProgramContext};
 
+pub use expandr_semantic::Escaping;
+
 pub type ModuleRegistry = HashMap<PathBuf, ProgramContext<Owned>>;
 
+#[derive(Debug, Clone, Default)]
+pub struct BuildOptions {
+    pub ast_logfile: Option<PathBuf>,
+    pub ctx_logfile: Option<PathBuf>,
+    pub token_logfile: Option<PathBuf>,
+
+    /// Escaping of interpolated values, unless the file has an `autoescape` directive
+    pub autoescape: Option<Escaping>,
+
+    /// Reindent interpolated multi-line values, also enabled by a `reindent` directive
+    pub reindent: bool,
+
+    /// Maximum nesting of mapping applications, `DEFAULT_MAX_DEPTH` if not set
+    pub max_depth: Option<usize>,
+
+    /// Memoize all mappings, otherwise only the ones in `memoize` directives are
+    pub memoize: bool,
+
+    /// Print statistics like memo cache hits to stderr
+    pub stats: bool,
+
+    /// Write the output while it is expanded instead of after the whole file succeeded. On an
+    /// error, the output written so far is left incomplete and the build fails. Without it, the
+    /// error is reported, nothing is written and the build goes on.
+    pub stream: bool,
+}
+
 // (Signature assumed based on context)
 pub fn build(
     path: PathBuf,
     source: String,
     output: &mut impl io::Write,
     registry: &mut HashMap<PathBuf, ProgramContext<Owned>>,
-    ast_logfile: Option<&PathBuf>,
-    ctx_logfile: Option<&PathBuf>,
-    token_logfile: Option<PathBuf>,
+    options: &BuildOptions,
 ) -> anyhow::Result<ProgramContext<Owned>> {
     // 1. Safe Path Parsing
     // handle non-UTF8 paths or root paths gracefully
@@ -37,18 +69,18 @@ pub fn build(
         .and_then(|s| s.to_str())
         .ok_or_else(|| anyhow!("Invalid filename or non-UTF8 path: {:?}", path))?;
 
-    let ast = get_ast(srcname.to_string(), &source, token_logfile)
+    let ast = get_ast(srcname.to_string(), &source, options.token_logfile.clone())
         .with_context(|| format!("Failed to parse AST for {:?}", path))?;
 
     // 2. Logging with Context (No expect/panic)
-    if let Some(ref file_path) = ast_logfile {
+    if let Some(ref file_path) = options.ast_logfile {
         let file = fs::File::create(file_path)
             .with_context(|| format!("Failed to create AST logfile at {:?}", file_path))?;
         write!(&file, "{:#?}", ast.exprs)
             .with_context(|| format!("Failed to write to AST logfile at {:?}", file_path))?;
     }
 
-    if let Some(ref file_path) = ctx_logfile {
+    if let Some(ref file_path) = options.ctx_logfile {
         let file = fs::File::create(file_path)
             .with_context(|| format!("Failed to create Context logfile at {:?}", file_path))?;
         write!(&file, "{:#?}", ast.ctx)
@@ -63,14 +95,14 @@ pub fn build(
             PathIdentRoot::File => path
                 .parent()
                 .ok_or_else(|| anyhow!("Source file {:?} has no parent directory", path))?
-                .join(&dep.path.path_parts[0]),
+                .join(dep.path.path_parts[0].as_str()),
             PathIdentRoot::Directory => {
                 let dep_file = dep
                     .path
                     .path_parts
                     .first()
                     .ok_or_else(|| anyhow!("Import path is empty: {:?}", dep))?;
-                path.with_file_name(dep_file).with_extension("exr")
+                path.with_file_name(dep_file.as_str()).with_extension("exr")
             }
             PathIdentRoot::Crate => todo!("Crate handling"),
         };
@@ -92,9 +124,7 @@ pub fn build(
                     dep_src,
                     &mut sink,
                     registry,
-                    None,
-                    None,
-                    None,
+                    &BuildOptions::default(),
                 )
                 .with_context(|| format!("Failed to compile dependency: {:?}", dep_path))?;
 
@@ -105,17 +135,91 @@ pub fn build(
 
     merge_contexts(&mut local_ctx, external_ctx);
 
-    match ast.expand(&local_ctx) {
-        Ok(Expanded::Str(out_str)) => {
-            output
-                .write_all(out_str.as_bytes())
-                .context("Failed to write expanded output to buffer")?;
+    // The file directive takes precedence over the option
+    let autoescape = match &ast.autoescape {
+        Some(mode) => Some(Escaping::from_name(mode).ok_or_else(|| {
+            anyhow!(
+                "Unknown autoescape mode `{mode}` in {path:?}, expected one of {ESCAPING_MODES}"
+            )
+        })?),
+        None => options.autoescape,
+    };
+
+    let expansion_options = ExpansionOptions {
+        autoescape,
+        reindent: options.reindent || ast.reindent,
+        max_depth: options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH),
+    };
+
+    let memo = MemoCache::new(options.memoize, ast.memoize.iter().copied());
+    let root_ctx = OptionsContext {
+        inner: &local_ctx,
+        options: expansion_options,
+    };
+    if options.stream {
+        let mut counting = CountingWriter {
+            inner: io::BufWriter::new(output),
+            written: 0,
+        };
+        let result = if memo.is_enabled() {
+            ast.expand_into(
+                &MemoContext {
+                    inner: &root_ctx,
+                    cache: &memo,
+                },
+                &mut counting,
+            )
+        } else {
+            ast.expand_into(&root_ctx, &mut counting)
+        };
+        counting
+            .flush()
+            .context("Failed to write expanded output")?;
+
+        if options.stats {
+            anstream::eprintln!("{srcname}.exr: {}", memo.stats());
+        }
+
+        // Output was already written, so there is nothing to recover
+        if let Err(e) = result {
+            return Err(anyhow!(e)).with_context(|| {
+                format!(
+                    "Failed to expand {srcname}.exr, the output is incomplete after {} bytes",
+                    counting.written
+                )
+            });
+        }
+    } else {
+        let result = if memo.is_enabled() {
+            ast.expand(&MemoContext {
+                inner: &root_ctx,
+                cache: &memo,
+            })
+        } else {
+            ast.expand(&root_ctx)
+        };
+
+        if options.stats {
+            anstream::eprintln!("{srcname}.exr: {}", memo.stats());
         }
-        Ok(_) => unreachable!(),
-        Err(e) => {
-            anstream::eprintln!(
-                "\nError in {srcname}.exr. Trying to recover. Error message:\n{e:#}"
-            );
+
+        match result {
+            Ok(Expanded::Str(out_str) | Expanded::Safe(out_str)) => {
+                output
+                    .write_all(out_str.as_bytes())
+                    .context("Failed to write expanded output to buffer")?;
+            }
+            Ok(Expanded::Int(i)) => {
+                return Err(anyhow!(
+                    "Expanding {srcname}.exr resulted in the integer {i} instead of text"
+                ))
+            }
+            // Nothing was written yet, the mappings of the file can still be used by others
+            Err(e) => {
+                anstream::eprintln!(
+                    "\nError in {srcname}.exr. Trying to recover. Error message:\n{e:#}"
+                );
+            }
         }
     }
 
@@ -124,6 +228,24 @@ pub fn build(
     Ok(local_ctx)
 }
 
+/// Counts the bytes passed through, to report how much of a streamed output was written
+struct CountingWriter<W> {
+    inner: W,
+    written: usize,
+}
+
+impl<W: io::Write> io::Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 fn get_ast<'s>(
     source_name: String,
     source: &'s str,
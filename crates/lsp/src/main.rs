@@ -9,6 +9,6 @@ async fn main() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::new(|client| ServerState::new(client));
+    let (service, socket) = LspService::new(ServerState::new);
     Server::new(stdin, stdout, socket).serve(service).await;
 }
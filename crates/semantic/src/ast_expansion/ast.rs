@@ -1,29 +1,56 @@
+use std::io;
+
 use expandr_syntax::ast::Ast;
+use reblessive::Stk;
 
 use super::*;
 
 use crate::{
     context::EvaluationContext,
-    expand::{Expandable, Expanded},
-    expansion_error::ExpansionResult,
+    expand::{write_text, Expandable, Expanded},
+    expansion_error::{ExpansionError, ExpansionResult},
 };
 
 impl<S: SourceType> Expandable for Ast<S> {
     /// Imports must be handled already and passed in as argument
-    fn expand<Ctx: EvaluationContext<Owned>>(self, imported_ctx: &Ctx) -> ExpansionResult {
-        let pieces = self.exprs.into_iter().map(|e| e.expand(imported_ctx));
+    async fn expand_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        imported_ctx: &Ctx,
+    ) -> ExpansionResult {
         let mut out_str = String::new();
 
-        // Expand all pieces, joining into string, collecting errors
-        for piece in pieces {
-            match piece? {
-                Expanded::Str(s) => out_str.push_str(&s),
-                Expanded::Int(i) => out_str.push(
-                    char::from_u32(i.try_into().expect("Negative number?"))
-                        .expect("This isn't a representable unicode character"),
-                ),
-            }
+        // Expand all pieces, joining into string
+        for expr in &self.exprs {
+            push_piece(&mut out_str, expr.expand_on(stk, imported_ctx).await?);
         }
         Ok(Expanded::Str(out_str))
     }
+
+    async fn expand_into_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        imported_ctx: &Ctx,
+        out: &mut dyn io::Write,
+    ) -> Result<Option<Expanded>, ExpansionError> {
+        for expr in &self.exprs {
+            if let Some(piece) = expr.expand_into_on(stk, imported_ctx, out).await? {
+                let mut text = String::new();
+                push_piece(&mut text, piece);
+                write_text(out, &text)?;
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Top level integers are written as the unicode character they encode
+fn push_piece(out_str: &mut String, piece: Expanded) {
+    match piece {
+        Expanded::Str(s) | Expanded::Safe(s) => out_str.push_str(&s),
+        Expanded::Int(i) => out_str.push(
+            char::from_u32(i.try_into().expect("Negative number?"))
+                .expect("This isn't a representable unicode character"),
+        ),
+    }
 }
@@ -1,19 +1,92 @@
-use expandr_syntax::ast::Block;
+use std::io;
 
-use crate::expand::Expanded;
+use expandr_syntax::ast::{Block, Expr};
+use reblessive::Stk;
+
+use crate::{
+    expand::{write_text, Expanded},
+    expansion_error::ExpansionError,
+};
 
 use super::*;
 
 impl<S: SourceType> Expandable for Block<S> {
-    fn expand<Ctx: EvaluationContext<Owned>>(self, ctx: &Ctx) -> ExpansionResult {
-        let mut result = String::new();
+    /// Lines are escaped like interpolations in autoescape mode, the separator, prefix and suffix
+    /// are taken literally like the text of a template
+    async fn expand_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+    ) -> ExpansionResult {
+        let autoescape = ctx.options().autoescape;
+        let format = &self.format;
+        let separator = match &format.separator {
+            Some(separator) => separator.expand_on(stk, ctx).await?.into_string(),
+            None => "\n".to_string(),
+        };
 
-        for expr in self.exprs {
-            result.push_str(&expr.expand(ctx)?.into_string());
-            result.push('\n');
+        let mut result = expand_text(&format.prefix, stk, ctx).await?;
+        for (i, expr) in self.exprs.iter().enumerate() {
+            if i > 0 {
+                result.push_str(&separator);
+            }
+            let value = expr.expand_on(stk, ctx).await?;
+            match autoescape {
+                Some(escaping) => result.push_str(&escaping.interpolate(value)),
+                None => result.push_str(&value.into_string()),
+            }
+        }
+        if format.trailing && !self.exprs.is_empty() {
+            result.push_str(&separator);
         }
+        result.push_str(&expand_text(&format.suffix, stk, ctx).await?);
 
-        Ok(Expanded::Str(result))
+        match autoescape {
+            Some(_) => Ok(Expanded::Safe(result)),
+            None => Ok(Expanded::Str(result)),
+        }
+    }
+
+    /// Only streamed without autoescaping, lines are escaped as a whole otherwise
+    async fn expand_into_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+        out: &mut dyn io::Write,
+    ) -> Result<Option<Expanded>, ExpansionError> {
+        if ctx.options().autoescape.is_some() {
+            return self.expand_on(stk, ctx).await.map(Some);
+        }
+        let format = &self.format;
+        let separator = match &format.separator {
+            Some(separator) => separator.expand_on(stk, ctx).await?.into_string(),
+            None => "\n".to_string(),
+        };
+
+        write_text(out, &expand_text(&format.prefix, stk, ctx).await?)?;
+        for (i, expr) in self.exprs.iter().enumerate() {
+            if i > 0 {
+                write_text(out, &separator)?;
+            }
+            if let Some(value) = expr.expand_into_on(stk, ctx, out).await? {
+                write_text(out, &value.into_string())?;
+            }
+        }
+        if format.trailing && !self.exprs.is_empty() {
+            write_text(out, &separator)?;
+        }
+        write_text(out, &expand_text(&format.suffix, stk, ctx).await?)?;
+        Ok(None)
     }
 }
 
+async fn expand_text<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    expr: &Option<Box<Expr<S>>>,
+    stk: &mut Stk,
+    ctx: &Ctx,
+) -> Result<String, ExpansionError> {
+    match expr {
+        Some(expr) => Ok(expr.expand_on(stk, ctx).await?.into_string()),
+        None => Ok(String::new()),
+    }
+}
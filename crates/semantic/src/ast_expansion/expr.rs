@@ -1,17 +1,30 @@
+use std::io;
+
 use expandr_syntax::ast::{mapping::MappingApplication, Expr};
+use reblessive::Stk;
 
 use super::*;
+use crate::{expand::Expanded, expansion_error::ExpansionError};
 
 impl<S: SourceType> Expandable for Expr<S> {
-    fn expand<Ctx: EvaluationContext<Owned>>(self, ctx: &Ctx) -> ExpansionResult {
+    /// Every nested expansion passes through here, so this is where they are pushed onto the
+    /// work stack. Each kind of expression gets a frame of its own size.
+    async fn expand_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+    ) -> ExpansionResult {
         use crate::expand::Expanded as E;
 
         match self {
-            Expr::String(val) => Ok(E::Str(val)),
+            Expr::String(val) => Ok(E::Str(val.clone())),
             Expr::StrRef(val) => Ok(E::Str(val.to_string())),
+            Expr::SafeString(val) => Ok(E::Safe(val.clone())),
 
-            Expr::TemplateString(tmpl_string) => tmpl_string.expand(ctx),
-            Expr::Integer(val) => Ok(E::Int(val)),
+            Expr::TemplateString(tmpl_string) => {
+                stk.run(|stk| tmpl_string.expand_on(stk, ctx)).await
+            }
+            Expr::Integer(val) => Ok(E::Int(*val)),
 
             Expr::PathIdent(ident) => {
                 // This branch is called when an argument is an Ident although the mapping expects
@@ -19,17 +32,44 @@ impl<S: SourceType> Expandable for Expr<S> {
                 // application without arguments), to allow writing things like `[m x + y]` instead
                 // of having to write `[m [x] + [y]]` explicitly.
                 let pseudo_mapping: MappingApplication<S> = MappingApplication {
-                    name: ident,
+                    name: ident.clone(),
                     args: vec![],
+                    named: vec![],
                 };
-                pseudo_mapping.expand(ctx)
+                stk.run(|stk| pseudo_mapping.expand_on(stk, ctx)).await
             }
             Expr::LiteralSymbol(s) => {
                 unreachable!("Should not try to expand a literal symbol: {s}")
             }
+            Expr::LiteralOperator(op) => {
+                unreachable!("Should not try to expand a literal operator: {op}")
+            }
+
+            Expr::Block(block) => stk.run(|stk| block.expand_on(stk, ctx)).await,
+            Expr::MappingApplication(mapping_application) => {
+                stk.run(|stk| mapping_application.expand_on(stk, ctx)).await
+            }
+            Expr::Is(is_expr) => stk.run(|stk| is_expr.expand_on(stk, ctx)).await,
+        }
+    }
 
-            Expr::Block(block) => block.expand(ctx),
-            Expr::MappingApplication(mapping_application) => mapping_application.expand(ctx),
+    async fn expand_into_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+        out: &mut dyn io::Write,
+    ) -> Result<Option<Expanded>, ExpansionError> {
+        match self {
+            Expr::TemplateString(tmpl_string) => {
+                stk.run(|stk| tmpl_string.expand_into_on(stk, ctx, out))
+                    .await
+            }
+            Expr::Block(block) => stk.run(|stk| block.expand_into_on(stk, ctx, out)).await,
+            Expr::MappingApplication(mapping_application) => {
+                stk.run(|stk| mapping_application.expand_into_on(stk, ctx, out))
+                    .await
+            }
+            _ => self.expand_on(stk, ctx).await.map(Some),
         }
     }
 }
@@ -0,0 +1,268 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::{context::ScopedContext, expand::Expanded, expansion_error::ExpansionError};
+use expandr_syntax::{
+    ast::{mapping::Mapping, IsBranch, IsExpr, IsPattern},
+    Symbol,
+};
+use reblessive::Stk;
+
+use super::*;
+
+/// Patterns:
+/// - `_`: anything
+/// - any expression: equal values (`1` also matches `'1'`)
+/// - `a or b`: either of both (`|` starts a comment)
+/// - `0..10`, `0..=9`, `'a'..='f'`, `10..`: ranges
+/// - `glob 'get_{name}*'`: `*` matches any text, `?` a single char, `{name}` any text that is
+///   bound to `name`
+/// - `name @ pattern`: binds the matched value to `name`
+///
+/// A branch can be guarded with `if cond` before the `?`. Bound names are visible in the guard and
+/// the translation.
+impl<S: SourceType> Expandable for IsExpr<S> {
+    async fn expand_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+    ) -> ExpansionResult {
+        let condition = self.expr.expand_on(stk, ctx).await?;
+
+        for branch in &self.branches {
+            for pattern in &branch.alternatives {
+                let Some(bindings) = pattern_matches(stk, ctx, pattern, &condition).await? else {
+                    continue;
+                };
+
+                if bindings.is_empty() {
+                    if guard_holds(branch, stk, ctx).await? {
+                        return branch.translation.expand_on(stk, ctx).await;
+                    }
+                    continue;
+                }
+
+                let mut scope = ScopedContext {
+                    parent: ctx,
+                    locals: HashMap::new(),
+                    lazy_locals: HashMap::new(),
+                };
+                for (name, value) in bindings {
+                    scope
+                        .locals
+                        .insert(name, vec![Mapping::SimpleMapping(value.into_expr())]);
+                }
+                if guard_holds(branch, stk, &scope).await? {
+                    return branch.translation.expand_on(stk, &scope).await;
+                }
+            }
+        }
+
+        // Expand to nothing if no branch matched
+        Ok(Expanded::Str(String::new()))
+    }
+}
+
+async fn guard_holds<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    branch: &IsBranch<S>,
+    stk: &mut Stk,
+    ctx: &Ctx,
+) -> Result<bool, ExpansionError> {
+    match &branch.guard {
+        Some(guard) => Ok(guard.expand_on(stk, ctx).await?.is_truthy()),
+        None => Ok(true),
+    }
+}
+
+/// Names bound by the pattern if it matches
+async fn pattern_matches<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    pattern: &IsPattern<S>,
+    value: &Expanded,
+) -> Result<Option<Vec<(Symbol, Expanded)>>, ExpansionError> {
+    Ok(match pattern {
+        IsPattern::Wildcard => Some(Vec::new()),
+        IsPattern::Value(expr) => {
+            values_equal(value, &expr.expand_on(stk, ctx).await?).then(Vec::new)
+        }
+        IsPattern::Range {
+            start,
+            end,
+            inclusive,
+        } => {
+            let above_start =
+                compare(value, &start.expand_on(stk, ctx).await?).is_some_and(Ordering::is_ge);
+            let below_end = match end {
+                Some(end) => compare(value, &end.expand_on(stk, ctx).await?)
+                    .is_some_and(|ord| ord.is_lt() || (*inclusive && ord.is_eq())),
+                None => true,
+            };
+            (above_start && below_end).then(Vec::new)
+        }
+        IsPattern::Glob(glob) => {
+            let glob = glob.expand_on(stk, ctx).await?.into_string();
+            let text = value.clone().into_string();
+            glob_match(&glob, &text).map(|captures| {
+                captures
+                    .into_iter()
+                    .map(|(name, captured)| (Symbol::intern(&name), Expanded::Str(captured)))
+                    .collect()
+            })
+        }
+        IsPattern::Binding { name, pattern } => stk
+            .run(|stk| pattern_matches(stk, ctx, pattern, value))
+            .await?
+            .map(|mut bindings| {
+                bindings.push((*name, value.clone()));
+                bindings
+            }),
+    })
+}
+
+fn as_int(value: &Expanded) -> Option<i64> {
+    match value {
+        Expanded::Int(i) => Some(*i),
+        Expanded::Str(s) | Expanded::Safe(s) => s.trim().parse().ok(),
+    }
+}
+
+fn values_equal(a: &Expanded, b: &Expanded) -> bool {
+    match (a, b) {
+        (Expanded::Int(a), Expanded::Int(b)) => a == b,
+        (Expanded::Int(i), s) | (s, Expanded::Int(i)) => s.clone().into_string() == i.to_string(),
+        (a, b) => a.clone().into_string() == b.clone().into_string(),
+    }
+}
+
+/// Numerically if both are integers (or strings holding one), lexicographically for strings
+fn compare(a: &Expanded, b: &Expanded) -> Option<Ordering> {
+    match (as_int(a), as_int(b)) {
+        (Some(a), Some(b)) => Some(a.cmp(&b)),
+        _ => match (a, b) {
+            (Expanded::Int(_), _) | (_, Expanded::Int(_)) => None,
+            (a, b) => Some(a.clone().into_string().cmp(&b.clone().into_string())),
+        },
+    }
+}
+
+enum GlobToken {
+    Char(char),
+    /// `?`
+    AnyChar,
+    /// `*` or `{name}`
+    Any(Option<String>),
+}
+
+fn glob_tokens(glob: &str) -> Vec<GlobToken> {
+    let mut tokens = Vec::new();
+    let mut chars = glob.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '?' => GlobToken::AnyChar,
+            '*' => GlobToken::Any(None),
+            // Unclosed `{` is taken literally
+            '{' if chars.as_str().contains('}') => {
+                let name = chars.by_ref().take_while(|&c| c != '}').collect();
+                GlobToken::Any(Some(name))
+            }
+            c => GlobToken::Char(c),
+        });
+    }
+    tokens
+}
+
+/// Match `text` against a glob, returning the `{name}` captures. Every `*` matches as little text
+/// as possible. Only the most recent `*` is ever extended, which keeps this in O(glob * text).
+fn glob_match(glob: &str, text: &str) -> Option<Vec<(String, String)>> {
+    let glob = glob_tokens(glob);
+    let text: Vec<char> = text.chars().collect();
+
+    // Text span of each `*` seen so far, and the glob position of the last one
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    let mut backtrack = None;
+    let (mut g, mut t) = (0, 0);
+
+    while t < text.len() {
+        match glob.get(g) {
+            Some(GlobToken::Any(_)) => {
+                spans.push((t, t));
+                backtrack = Some(g);
+                g += 1;
+            }
+            Some(GlobToken::AnyChar) => (g, t) = (g + 1, t + 1),
+            Some(GlobToken::Char(c)) if *c == text[t] => (g, t) = (g + 1, t + 1),
+            _ => {
+                let star = backtrack?;
+                let span = spans.last_mut().expect("A backtrack point has a span");
+                span.1 += 1;
+                (g, t) = (star + 1, span.1);
+            }
+        }
+    }
+    while let Some(GlobToken::Any(_)) = glob.get(g) {
+        spans.push((t, t));
+        g += 1;
+    }
+    if g != glob.len() {
+        return None;
+    }
+
+    let names = glob.into_iter().filter_map(|token| match token {
+        GlobToken::Any(name) => Some(name),
+        _ => None,
+    });
+    Some(
+        names
+            .zip(spans)
+            .filter_map(|(name, (start, end))| Some((name?, text[start..end].iter().collect())))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        assert_eq!(glob_match("get_*", "get_name"), Some(vec![]));
+        assert_eq!(glob_match("get_*", "set_name"), None);
+        assert_eq!(glob_match("*.rs", "main.rs"), Some(vec![]));
+        assert_eq!(glob_match("r?x", "rax"), Some(vec![]));
+        assert_eq!(glob_match("r?x", "rx"), None);
+        assert_eq!(
+            glob_match("{name}.{ext}", "archive.tar.gz"),
+            Some(vec![
+                ("name".to_string(), "archive".to_string()),
+                ("ext".to_string(), "tar.gz".to_string())
+            ])
+        );
+        assert_eq!(glob_match("{x", "{x"), Some(vec![]));
+        assert_eq!(
+            glob_match("{a}-{b}", "x-y-z"),
+            Some(vec![
+                ("a".to_string(), "x".to_string()),
+                ("b".to_string(), "y-z".to_string())
+            ])
+        );
+        assert_eq!(glob_match("*a", "ba"), Some(vec![]));
+        assert_eq!(glob_match("a*", ""), None);
+        assert_eq!(glob_match("**", ""), Some(vec![]));
+
+        // Neither blows the stack nor takes exponential time
+        let long = "a".repeat(100_000);
+        assert_eq!(glob_match("*a*a*a*b", &long), None);
+        assert_eq!(glob_match("?*a", &long), Some(vec![]));
+    }
+
+    #[test]
+    fn ranges_and_values() {
+        let int = Expanded::Int;
+        let str = |s: &str| Expanded::Str(s.to_string());
+        assert!(values_equal(&int(1), &str("1")));
+        assert!(!values_equal(&int(1), &str("01x")));
+        assert_eq!(compare(&str("10"), &int(9)), Some(Ordering::Greater));
+        assert_eq!(compare(&str("b"), &str("a")), Some(Ordering::Greater));
+        assert_eq!(compare(&str("b"), &int(1)), None);
+    }
+}
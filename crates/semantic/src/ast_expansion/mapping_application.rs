@@ -1,101 +1,298 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, io};
 
 use expandr_syntax::{
     ast::{
-        mapping::{Mapping, MappingApplication, Param, ParamType},
+        mapping::{Guard, Mapping, MappingApplication, Param, ParamType, Params},
         Expr,
     },
-    log, IntoOwned as _,
+    log, IntoOwned as _, Symbol,
 };
+use reblessive::Stk;
 
 use super::*;
 
-use crate::{builtins::get_builtin, context::ScopedContext, expand::Expanded, undefined_mapping};
+use crate::{
+    builtins::{apply_builtin, is_pure},
+    context::{CallContext, ScopedContext},
+    expand::Expanded,
+    expansion_error::ExpansionError,
+    mapping_lookup::ArgValues,
+    memo::MemoCache,
+    recursion_limit, undefined_mapping,
+};
 
 impl<S: SourceType> Expandable for MappingApplication<S> {
-    fn expand<Ctx>(self, ctx: &Ctx) -> ExpansionResult
+    async fn expand_on<Ctx>(&self, stk: &mut Stk, ctx: &Ctx) -> ExpansionResult
+    where
+        Ctx: EvaluationContext<Owned>,
+    {
+        apply(self, stk, ctx, async |stk, translation, scope| {
+            translation.expand_on(stk, &scope).await
+        })
+        .await
+    }
+
+    async fn expand_into_on<Ctx>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+        out: &mut dyn io::Write,
+    ) -> Result<Option<Expanded>, ExpansionError>
     where
         Ctx: EvaluationContext<Owned>,
     {
-        log!(
-            "Trying to resolve `{}` with args {:#?}",
-            self.name,
-            self.args
-        );
-        if let Some(builtin) = get_builtin(self.name.name()) {
-            return builtin(ctx, &self.args);
-        } else {
-            log!("No builtin found for `{}`", self.name);
+        apply(self, stk, ctx, async |stk, translation, scope| {
+            translation.expand_into_on(stk, &scope, out).await
+        })
+        .await
+    }
+}
+
+/// Resolve the application and expand the translation of the matching definition with
+/// `expand` in the scope of its parameters. Builtins and memoized applications are expanded
+/// to a value right away.
+async fn apply<S: SourceType, Ctx, R>(
+    appl: &MappingApplication<S>,
+    stk: &mut Stk,
+    ctx: &Ctx,
+    expand: impl AsyncFnOnce(
+        &mut Stk,
+        &Expr<Owned>,
+        &dyn EvaluationContext<Owned>,
+    ) -> Result<R, ExpansionError>,
+) -> Result<R, ExpansionError>
+where
+    Ctx: EvaluationContext<Owned>,
+    R: From<Expanded>,
+{
+    log!(
+        "Trying to resolve `{}` with args {:#?}",
+        appl.name,
+        appl.args
+    );
+    // Arguments bound to lazy parameters are expanded in the scope they were passed from
+    if appl.args.is_empty() {
+        if let Some((expr, caller_ctx)) = ctx.lookup_lazy(&appl.name) {
+            log!("Expanding lazy argument `{}`", appl.name);
+            return expand(stk, expr, caller_ctx).await;
         }
-        let owned_args: Vec<_> = self
-            .args
-            .clone() // You can easily get rid of that one
-            .into_iter()
-            .map(expandr_syntax::IntoOwned::into_owned)
-            .collect();
-
-        let Some(mapping) = ctx.lookup(&self.name, &owned_args) else {
-            log!("No matching found");
-            undefined_mapping!("Lookup failed", self.name, owned_args)?
-        };
-
-        log!(
-            "Inserting previously resolved definition for `{}`",
-            self.name
-        );
-
-        match mapping {
-            Mapping::SimpleMapping(translation) => translation.clone().expand(ctx),
-            Mapping::ParameterizedMapping(mapping) => {
-                let mut args = self.args.into_iter();
-                let mut tmp_ctx = ScopedContext {
-                    parent: ctx,
-                    locals: HashMap::new(),
-                };
-                for param in &mapping.params.entries {
-                    match param {
-                        Param::ParamExpr { name, typ, rep } => match rep {
-                            None => {
-                                let next_arg = args
-                                    .next()
-                                    .expect("Not enough args for the given parameters");
-
-                                let new_entry = Mapping::SimpleMapping(match typ {
-                                    ParamType::Expr => match next_arg.expand(ctx)? {
-                                        Expanded::Str(x) => Expr::String::<S>(x),
-                                        Expanded::Int(x) => Expr::Integer(x),
-                                    },
-                                    ParamType::Ident => {
-                                        let strval = match next_arg {
-                                            Expr::PathIdent(id) => id.original_src,
-                                            Expr::MappingApplication(appl)
-                                                if appl.args.is_empty() =>
-                                            {
-                                                appl.expand(ctx)?.into_string()
-                                            }
-                                            _ => unreachable!("Expected an ident"),
-                                        };
-                                        Expr::String(strval)
-                                    }
-                                });
-
-                                tmp_ctx
-                                    .locals
-                                    .entry(name.to_string())
-                                    .or_default()
-                                    .push(new_entry.into_owned());
-                            }
-                            Some(_) => todo!(),
-                        },
-                        Param::Symbol(_) | Param::Ident(_) => {
-                            args.next();
-                        }
-                    }
-                }
+    }
+
+    let owned_args = S::owned_exprs(&appl.args);
+    let owned_named: Vec<_> = appl
+        .named
+        .iter()
+        .map(|arg| arg.clone().into_owned())
+        .collect();
+    let args = ArgValues::new(&owned_args, ctx).with_named(&owned_named);
+
+    // User defined mappings shadow builtins
+    let Some((mapping, scope)) = args.resolve(stk, &appl.name).await? else {
+        let name = appl.name.canonical();
+        if let Some(memo) = ctx.memo().filter(|_| !is_pure(name.as_str())) {
+            memo.mark_impure();
+        }
+        if let Some(result) = apply_builtin(name.as_str(), stk, ctx, &appl.args).await {
+            return result.map(R::from);
+        }
+        log!("No matching or builtin found");
+        undefined_mapping!("Lookup failed", appl.name, owned_args.to_vec())?
+    };
+
+    log!(
+        "Inserting previously resolved definition for `{}`",
+        appl.name
+    );
+
+    let depth = ctx.depth() + 1;
+    let options = ctx.options();
+    if depth > options.max_depth {
+        return recursion_limit!(options.max_depth, ctx);
+    }
+    let call_ctx = CallContext {
+        inner: scope,
+        caller: ctx,
+        name: &appl.name,
+        depth,
+        options,
+        memo: ctx.memo(),
+    };
+
+    match mapping {
+        Mapping::SimpleMapping(translation) => expand(stk, translation, &call_ctx).await,
+        Mapping::ParameterizedMapping(mapping) => {
+            let mut tmp_ctx = ScopedContext {
+                parent: &call_ctx,
+                locals: HashMap::new(),
+                lazy_locals: HashMap::new(),
+            };
+            let memo = call_ctx
+                .memo
+                .filter(|memo| memo.memoizes(&appl.name, mapping));
+            // Expanded arguments, only collected for the memo key
+            let mut values = memo.map(|_| Vec::new());
+            bind_args(stk, &mapping.params, &args, &mut tmp_ctx, values.as_mut()).await?;
+
+            match (memo, values) {
+                (Some(memo), Some(values)) => memo
+                    .get_or_expand(
+                        MemoCache::key(mapping, values, options),
+                        mapping.translation.expand_on(stk, &tmp_ctx),
+                    )
+                    .await
+                    .map(R::from),
+                _ => expand(stk, &mapping.translation, &tmp_ctx).await,
+            }
+        }
+    }
+}
 
-                let owned: Expr<Owned> = mapping.translation.clone().into_owned();
-                owned.expand(&tmp_ctx)
+/// Bind the arguments of an application to `params`, named arguments by their name. Optional
+/// parameters without an argument are bound to their default value.
+async fn bind_args<'c>(
+    stk: &mut Stk,
+    params: &Params,
+    args: &ArgValues<'c>,
+    scope: &mut ScopedContext<'c, Owned>,
+    mut values: Option<&mut Vec<Expanded>>,
+) -> Result<(), ExpansionError> {
+    let slots = args
+        .arrange(params)
+        .expect("Lookup only matches arguments that fit the parameters");
+    for (param, slot) in params.entries.iter().zip(slots) {
+        match slot {
+            Some(i) => {
+                bind_param(stk, param, args.arg(i), args, scope, values.as_deref_mut()).await?
             }
+            None => bind_default(stk, param, scope, values.as_deref_mut()).await?,
         }
     }
+    Ok(())
+}
+
+/// Bind `exprs` to the captures in `params`, recursing into structural patterns. Arguments are
+/// expanded in the scope of the caller, unless matching did so already. The bound values are
+/// also pushed to `values` if given.
+async fn bind_params<'c>(
+    stk: &mut Stk,
+    params: &Params,
+    exprs: &[Expr<Owned>],
+    args: &ArgValues<'c>,
+    scope: &mut ScopedContext<'c, Owned>,
+    mut values: Option<&mut Vec<Expanded>>,
+) -> Result<(), ExpansionError> {
+    assert_eq!(
+        params.entries.len(),
+        exprs.len(),
+        "Wrong number of args for the given parameters"
+    );
+    for (param, arg) in params.entries.iter().zip(exprs) {
+        bind_param(stk, param, arg, args, scope, values.as_deref_mut()).await?;
+    }
+    Ok(())
+}
+
+async fn bind_param<'c>(
+    stk: &mut Stk,
+    param: &Param,
+    arg: &Expr<Owned>,
+    args: &ArgValues<'c>,
+    scope: &mut ScopedContext<'c, Owned>,
+    values: Option<&mut Vec<Expanded>>,
+) -> Result<(), ExpansionError> {
+    match param {
+        Param::ParamExpr {
+            name,
+            typ,
+            rep,
+            guard,
+            ..
+        } => match rep {
+            None => {
+                let value = match (typ, guard) {
+                    // Regex guards match the text of the argument, which is also what is bound
+                    (_, Some(Guard::Regex { .. })) => Expanded::Str(args.text(stk, arg).await?),
+                    (ParamType::Lazy, _) => {
+                        scope
+                            .lazy_locals
+                            .insert(name.name(), (arg.clone(), args.ctx));
+                        return Ok(());
+                    }
+                    (typ, _) => args.bound_value(stk, arg, *typ).await?,
+                };
+                bind_value(name.name(), value, scope, values);
+            }
+            Some(_) => unreachable!("Lookup doesn't match repeated parameters"),
+        },
+        Param::Application { params, .. } => {
+            let Expr::MappingApplication(appl) = arg else {
+                unreachable!("Lookup only matches applications against application patterns")
+            };
+            stk.run(|stk| bind_params(stk, params, &appl.args, args, scope, values))
+                .await?;
+        }
+        Param::Block(params) => {
+            let Expr::Block(block) = arg else {
+                unreachable!("Lookup only matches blocks against block patterns")
+            };
+            stk.run(|stk| bind_params(stk, params, &block.exprs, args, scope, values))
+                .await?;
+        }
+        Param::Symbol(_)
+        | Param::Operator(_)
+        | Param::Ident(_)
+        | Param::Integer(_)
+        | Param::String(_) => {}
+    }
+    Ok(())
+}
+
+/// Bind an optional parameter to its default value, which is expanded in the scope the mapping is
+/// defined in
+async fn bind_default<'c>(
+    stk: &mut Stk,
+    param: &Param,
+    scope: &mut ScopedContext<'c, Owned>,
+    values: Option<&mut Vec<Expanded>>,
+) -> Result<(), ExpansionError> {
+    let Param::ParamExpr {
+        name,
+        typ,
+        default: Some(default),
+        ..
+    } = param
+    else {
+        unreachable!("Only optional parameters are left without an argument")
+    };
+    let defining_scope = scope.parent;
+    let value = match (typ, &default.value) {
+        (ParamType::Lazy, value) => {
+            scope
+                .lazy_locals
+                .insert(name.name(), (value.clone(), defining_scope));
+            return Ok(());
+        }
+        (ParamType::Ident, Expr::PathIdent(id)) => Expanded::Str(id.original_src.to_string()),
+        (_, Expr::LiteralSymbol(c)) => Expanded::Str(c.to_string()),
+        (_, Expr::LiteralOperator(op)) => Expanded::Str(op.to_string()),
+        (_, value) => value.expand_on(stk, &defining_scope).await?,
+    };
+    bind_value(name.name(), value, scope, values);
+    Ok(())
+}
+
+fn bind_value(
+    name: Symbol,
+    value: Expanded,
+    scope: &mut ScopedContext<'_, Owned>,
+    values: Option<&mut Vec<Expanded>>,
+) {
+    if let Some(values) = values {
+        values.push(value.clone());
+    }
+    scope
+        .locals
+        .entry(name)
+        .or_default()
+        .push(Mapping::SimpleMapping(value.into_expr()));
 }
@@ -1,8 +1,6 @@
 // Used by all files in this module
-pub(self) use crate::{
-    context::EvaluationContext, expand::Expandable, expansion_error::ExpansionResult,
-};
-pub(self) use expandr_syntax::source_type::{Owned, SourceType};
+use crate::{context::EvaluationContext, expand::Expandable, expansion_error::ExpansionResult};
+use expandr_syntax::source_type::{Owned, SourceType};
 
 #[macro_export]
 macro_rules! derive_from {
@@ -32,5 +30,6 @@ macro_rules! derive_from {
 mod ast;
 mod block;
 mod expr;
+mod is_expr;
 mod mapping_application;
 mod template_string;
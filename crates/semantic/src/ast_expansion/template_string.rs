@@ -1,20 +1,125 @@
+use std::io;
+
 use expandr_syntax::ast::{Expr, TemplatePiece, TemplateString};
+use reblessive::Stk;
 
-use crate::expand::Expanded;
+use crate::{
+    expand::{write_text, Expanded},
+    expansion_error::ExpansionError,
+};
 
 use super::*;
 
 impl<S: SourceType> Expandable for TemplateString<S> {
-    fn expand<Ctx: EvaluationContext<Owned>>(self, ctx: &Ctx) -> ExpansionResult {
+    /// In autoescape mode, interpolated values are escaped and a template that interpolates
+    /// anything expands to safe text. Templates without interpolations are plain text, like string
+    /// literals.
+    async fn expand_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+    ) -> ExpansionResult {
+        let options = ctx.options();
+        let mut interpolates = false;
+
         let mut result = String::new();
-        for piece in self.pieces.into_iter() {
+        for piece in &self.pieces {
             match piece {
-                TemplatePiece::Char(ch) => result.push(ch),
+                TemplatePiece::Char(ch) => result.push(*ch),
                 TemplatePiece::StrVal(s) => result.push_str(s.as_ref()),
                 TemplatePiece::Expr(Expr::PathIdent(id)) => result.push_str(&id.to_string()),
-                TemplatePiece::Expr(expr) => result.push_str(&expr.expand(ctx)?.into_string()),
+                TemplatePiece::Expr(expr) => {
+                    interpolates = true;
+                    let value = expr.expand_on(stk, ctx).await?;
+                    let value = match options.autoescape {
+                        Some(escaping) => escaping.interpolate(value),
+                        None => value.into_string(),
+                    };
+                    match options.reindent {
+                        true => push_reindented(&mut result, &value),
+                        false => result.push_str(&value),
+                    }
+                }
+            }
+        }
+
+        match options.autoescape {
+            Some(_) if interpolates => Ok(Expanded::Safe(result)),
+            _ => Ok(Expanded::Str(result)),
+        }
+    }
+
+    /// Escaping and reindenting need the whole value of an interpolation, the template is only
+    /// streamed without them
+    async fn expand_into_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+        out: &mut dyn io::Write,
+    ) -> Result<Option<Expanded>, ExpansionError> {
+        let options = ctx.options();
+        if options.autoescape.is_some() || options.reindent {
+            return self.expand_on(stk, ctx).await.map(Some);
+        }
+
+        for piece in &self.pieces {
+            match piece {
+                TemplatePiece::Char(ch) => write_text(out, ch.encode_utf8(&mut [0; 4]))?,
+                TemplatePiece::StrVal(s) => write_text(out, s.as_ref())?,
+                TemplatePiece::Expr(Expr::PathIdent(id)) => write_text(out, &id.to_string())?,
+                TemplatePiece::Expr(expr) => {
+                    if let Some(value) = expr.expand_into_on(stk, ctx, out).await? {
+                        write_text(out, &value.into_string())?;
+                    }
+                }
             }
         }
-        Ok(Expanded::Str(result))
+        Ok(None)
+    }
+}
+
+/// Push `value`, indenting all lines after the first to the column it is interpolated at. Whitespace
+/// before the interpolation is kept, so tabs stay tabs, other characters count as one column each.
+/// Empty lines stay empty.
+fn push_reindented(result: &mut String, value: &str) {
+    let line_start = result.rfind('\n').map_or(0, |i| i + 1);
+    let indent: String = result[line_start..]
+        .chars()
+        .map(|c| if c.is_whitespace() { c } else { ' ' })
+        .collect();
+
+    let mut lines = value.split('\n');
+    if let Some(first) = lines.next() {
+        result.push_str(first);
+    }
+    for line in lines {
+        result.push('\n');
+        if !line.is_empty() {
+            result.push_str(&indent);
+            result.push_str(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reindented(before: &str, value: &str) -> String {
+        let mut result = before.to_string();
+        push_reindented(&mut result, value);
+        result
+    }
+
+    #[test]
+    fn reindent() {
+        assert_eq!(reindented("<p>\n\t", "a\nb\n"), "<p>\n\ta\n\tb\n");
+        assert_eq!(
+            reindented("  x = ", "[\n  1,\n]"),
+            "  x = [\n        1,\n      ]"
+        );
+        assert_eq!(reindented("\t(f ", "a\nb"), "\t(f a\n\t   b");
+        assert_eq!(reindented("    ", "a\n\nb"), "    a\n\n    b");
+        assert_eq!(reindented("", "a\nb"), "a\nb");
     }
 }
@@ -1,47 +1,536 @@
-use std::ops;
+use std::{borrow::Cow, fmt};
 
+use super::expand_value;
 use crate::{
+    builtin_failed,
     context::EvaluationContext,
     expand::{Expandable as _, Expanded},
-    expansion_error::ExpansionResult,
+    expansion_error::{ExpansionError, ExpansionResult},
 };
 use expandr_syntax::{
     ast::{mapping::Args, Expr},
     source_type::{Owned, SourceType},
 };
+use reblessive::Stk;
 
-pub fn evaluate_math<S: SourceType, Ctx: EvaluationContext<Owned>>(
+/// Integer expressions with the usual precedence (lowest first):
+///
+/// - `==`, `!=`, `<`, `<=`, `>`, `>=` (not chainable, `1` if true, `0` otherwise)
+/// - `bor`
+/// - `^` / `bxor`
+/// - `&` / `band`
+/// - `<<` / `shl`, `>>` / `shr`
+/// - `+`, `-`
+/// - `*`, `/`, `%`
+/// - unary `-`, `~` / `bnot`
+/// - `**` (right associative)
+///
+/// Groups are written as `( .. )` or as nested `[ .. ]`, starting with an integer. Strings holding
+/// an integer are treated as that integer, any other string can only be compared.
+///
+/// `|` can't be used for bitwise or, as it starts a comment.
+pub async fn evaluate_math<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
     ctx: &Ctx,
     args: &Args<S>,
 ) -> ExpansionResult {
-    let op = match args.get(1) {
-        Some(Expr::LiteralSymbol('+')) => <i64 as ops::Add>::add,
-        Some(Expr::LiteralSymbol('-')) => ops::Sub::sub,
-        Some(Expr::LiteralSymbol('*')) => ops::Mul::mul,
-        Some(Expr::LiteralSymbol('/')) => ops::Div::div,
-        _ => todo!("No such operation {args:?}"),
+    let tokens = match tokenize(stk, ctx, args).await? {
+        Ok(tokens) => tokens,
+        Err(msg) => return builtin_failed!("calc", msg, args),
     };
-    Ok(match &args[..] {
-        [a, Expr::LiteralSymbol('*'), b] => {
-            match (a.clone().expand(ctx)?, b.clone().expand(ctx)?) {
-                (Expanded::Int(a), Expanded::Int(b)) => Expanded::Int(a * b),
-                (Expanded::Str(s), Expanded::Int(n)) | (Expanded::Int(n), Expanded::Str(s)) => {
-                    Expanded::Str(
-                        s.repeat(
-                            n.try_into()
-                                .expect("Cannot multiply String by negative number"),
-                        ),
-                    )
+    let mut calc = Calculator { tokens, pos: 0 };
+    match calc.evaluate() {
+        Ok(value) => Ok(value),
+        Err(msg) => builtin_failed!("calc", msg, args),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CalcToken {
+    Value(Expanded),
+    Op(Op),
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// The token as it is written in a calculation
+impl fmt::Display for CalcToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalcToken::Value(value) => f.write_str(&value_text(value)),
+            CalcToken::Op(op) => f.write_str(op.symbol()),
+            CalcToken::Open => f.write_str("("),
+            CalcToken::Close => f.write_str(")"),
+        }
+    }
+}
+
+/// Integers as they are, strings quoted
+fn value_text(value: &Expanded) -> String {
+    match value {
+        Expanded::Int(n) => n.to_string(),
+        Expanded::Str(s) | Expanded::Safe(s) => format!("'{s}'"),
+    }
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Rem => "%",
+            Op::Pow => "**",
+            Op::BitAnd => "&",
+            Op::BitOr => "bor",
+            Op::BitXor => "^",
+            Op::BitNot => "~",
+            Op::Shl => "<<",
+            Op::Shr => ">>",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+
+    fn is_comparison(self) -> bool {
+        matches!(self, Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge)
+    }
+
+    /// Left and right binding power of binary operators
+    fn binding_power(self) -> Option<(u8, u8)> {
+        Some(match self {
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => (1, 2),
+            Op::BitOr => (3, 4),
+            Op::BitXor => (5, 6),
+            Op::BitAnd => (7, 8),
+            Op::Shl | Op::Shr => (9, 10),
+            Op::Add | Op::Sub => (11, 12),
+            Op::Mul | Op::Div | Op::Rem => (13, 14),
+            Op::Pow | Op::BitNot => return None,
+        })
+    }
+}
+
+/// Turn the arguments into tokens, expanding operands on the way. The outer result carries errors
+/// of the operand expansion, the inner one invalid arguments.
+async fn tokenize<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> Result<Result<Vec<CalcToken>, String>, ExpansionError> {
+    let mut tokens = Vec::new();
+    // Operators written without space (`**`, `<=`) are split up into their symbols again
+    let mut args = args
+        .iter()
+        .flat_map(|arg| match arg {
+            Expr::LiteralOperator(op) => op
+                .as_str()
+                .chars()
+                .map(|c| Cow::Owned(Expr::LiteralSymbol(c)))
+                .collect(),
+            arg => vec![Cow::Borrowed(arg)],
+        })
+        .peekable();
+
+    while let Some(arg) = args.next() {
+        let mut next_is = |c: char| {
+            args.next_if(|next| matches!(next.as_ref(), Expr::LiteralSymbol(n) if *n == c))
+                .is_some()
+        };
+        tokens.push(match arg.as_ref() {
+            Expr::LiteralSymbol('+') => CalcToken::Op(Op::Add),
+            Expr::LiteralSymbol('-') => CalcToken::Op(Op::Sub),
+            Expr::LiteralSymbol('*') if next_is('*') => CalcToken::Op(Op::Pow),
+            Expr::LiteralSymbol('*') => CalcToken::Op(Op::Mul),
+            Expr::LiteralSymbol('/') => CalcToken::Op(Op::Div),
+            Expr::LiteralSymbol('%') => CalcToken::Op(Op::Rem),
+            Expr::LiteralSymbol('&') => CalcToken::Op(Op::BitAnd),
+            Expr::LiteralSymbol('^') => CalcToken::Op(Op::BitXor),
+            Expr::LiteralSymbol('~') => CalcToken::Op(Op::BitNot),
+            Expr::LiteralSymbol('<') if next_is('<') => CalcToken::Op(Op::Shl),
+            Expr::LiteralSymbol('<') if next_is('=') => CalcToken::Op(Op::Le),
+            Expr::LiteralSymbol('<') => CalcToken::Op(Op::Lt),
+            Expr::LiteralSymbol('>') if next_is('>') => CalcToken::Op(Op::Shr),
+            Expr::LiteralSymbol('>') if next_is('=') => CalcToken::Op(Op::Ge),
+            Expr::LiteralSymbol('>') => CalcToken::Op(Op::Gt),
+            Expr::LiteralSymbol('=') if next_is('=') => CalcToken::Op(Op::Eq),
+            Expr::LiteralSymbol('!') if next_is('=') => CalcToken::Op(Op::Ne),
+            Expr::LiteralSymbol('(') => CalcToken::Open,
+            Expr::LiteralSymbol(')') => CalcToken::Close,
+            Expr::LiteralSymbol(s) => return Ok(Err(format!("Unknown operator `{s}`"))),
+            Expr::PathIdent(id) if id.original_src.as_str() == "band" => CalcToken::Op(Op::BitAnd),
+            Expr::PathIdent(id) if id.original_src.as_str() == "bor" => CalcToken::Op(Op::BitOr),
+            Expr::PathIdent(id) if id.original_src.as_str() == "bxor" => CalcToken::Op(Op::BitXor),
+            Expr::PathIdent(id) if id.original_src.as_str() == "bnot" => CalcToken::Op(Op::BitNot),
+            Expr::PathIdent(id) if id.original_src.as_str() == "shl" => CalcToken::Op(Op::Shl),
+            Expr::PathIdent(id) if id.original_src.as_str() == "shr" => CalcToken::Op(Op::Shr),
+            operand => CalcToken::Value(operand_value(operand.expand_on(stk, ctx).await?)),
+        });
+    }
+    Ok(Ok(tokens))
+}
+
+/// Strings holding an integer are that integer
+fn operand_value(value: Expanded) -> Expanded {
+    match value {
+        Expanded::Str(s) | Expanded::Safe(s) => match s.trim().parse::<i64>() {
+            Ok(n) => Expanded::Int(n),
+            Err(_) => Expanded::Str(s),
+        },
+        int => int,
+    }
+}
+
+struct Calculator {
+    tokens: Vec<CalcToken>,
+    pos: usize,
+}
+
+impl Calculator {
+    fn evaluate(&mut self) -> Result<Expanded, String> {
+        if self.tokens.is_empty() {
+            return Err("Empty expression".to_string());
+        }
+        let value = self.binary(0)?;
+        match self.tokens.get(self.pos) {
+            None => Ok(value),
+            Some(tok) => Err(format!("Unexpected `{tok}` after complete expression")),
+        }
+    }
+
+    fn peek(&self) -> Option<&CalcToken> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<CalcToken> {
+        let tok = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        tok
+    }
+
+    /// Binary operators, precedence climbing
+    fn binary(&mut self, min_bp: u8) -> Result<Expanded, String> {
+        let mut lhs = self.unary()?;
+        let mut compared = false;
+        while let Some(CalcToken::Op(op)) = self.peek() {
+            let op = *op;
+            let Some((l_bp, r_bp)) = op.binding_power() else {
+                return Err(format!("`{}` is not a binary operator", op.symbol()));
+            };
+            if l_bp < min_bp {
+                break;
+            }
+            if op.is_comparison() {
+                if compared {
+                    return Err("Comparisons can't be chained".to_string());
+                }
+                compared = true;
+            }
+            self.next();
+            let rhs = self.binary(r_bp)?;
+            lhs = apply_binary(op, lhs, rhs)?;
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expanded, String> {
+        match self.peek() {
+            Some(CalcToken::Op(Op::Sub)) => {
+                self.next();
+                let n = as_int(Op::Sub, self.unary()?)?;
+                n.checked_neg()
+                    .map(Expanded::Int)
+                    .ok_or_else(|| format!("Overflow in -{n}"))
+            }
+            Some(CalcToken::Op(Op::Add)) => {
+                self.next();
+                self.unary()
+            }
+            Some(CalcToken::Op(Op::BitNot)) => {
+                self.next();
+                Ok(Expanded::Int(!as_int(Op::BitNot, self.unary()?)?))
+            }
+            _ => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Expanded, String> {
+        let base = self.atom()?;
+        if let Some(CalcToken::Op(Op::Pow)) = self.peek() {
+            self.next();
+            // Right associative, and `2 ** -1` should be reported as negative exponent
+            let exponent = self.unary()?;
+            return apply_binary(Op::Pow, base, exponent);
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expanded, String> {
+        match self.next() {
+            Some(CalcToken::Value(value)) => Ok(value),
+            Some(CalcToken::Open) => {
+                let value = self.binary(0)?;
+                match self.next() {
+                    Some(CalcToken::Close) => Ok(value),
+                    _ => Err("Missing `)`".to_string()),
                 }
-                _ => panic!("Operation '*' not defined for String and String"),
             }
+            Some(tok) => Err(format!("Expected a value, found `{tok}`")),
+            None => Err("Expected a value, found end of expression".to_string()),
+        }
+    }
+}
+
+fn as_int(op: Op, value: Expanded) -> Result<i64, String> {
+    match value {
+        Expanded::Int(n) => Ok(n),
+        Expanded::Str(s) | Expanded::Safe(s) => Err(format!(
+            "Operation `{}` needs integer operands, found {s:?}",
+            op.symbol()
+        )),
+    }
+}
+
+fn apply_binary(op: Op, lhs: Expanded, rhs: Expanded) -> Result<Expanded, String> {
+    if op.is_comparison() {
+        return compare(op, &lhs, &rhs).map(|res| Expanded::Int(res as i64));
+    }
+
+    let (a, b) = (as_int(op, lhs)?, as_int(op, rhs)?);
+    let overflow = || format!("Overflow in {a} {} {b}", op.symbol());
+    let result = match op {
+        Op::Add => a.checked_add(b).ok_or_else(overflow)?,
+        Op::Sub => a.checked_sub(b).ok_or_else(overflow)?,
+        Op::Mul => a.checked_mul(b).ok_or_else(overflow)?,
+        Op::Div | Op::Rem if b == 0 => {
+            return Err(format!("Division by zero in {a} {} 0", op.symbol()))
         }
-        [a, Expr::LiteralSymbol('+' | '-' | '/'), b] => {
-            match (a.clone().expand(ctx)?, b.clone().expand(ctx)?) {
-                (Expanded::Int(a), Expanded::Int(b)) => Expanded::Int(op(a, b)),
-                _ => panic!("Operation {:?} only implemented for integers", args[1]),
+        Op::Div => a.checked_div(b).ok_or_else(overflow)?,
+        Op::Rem => a.checked_rem(b).ok_or_else(overflow)?,
+        Op::Pow => {
+            let exp = u32::try_from(b).map_err(|_| format!("Invalid exponent in {a} ** {b}"))?;
+            a.checked_pow(exp).ok_or_else(overflow)?
+        }
+        Op::BitAnd => a & b,
+        Op::BitOr => a | b,
+        Op::BitXor => a ^ b,
+        Op::Shl | Op::Shr => {
+            let shift = u32::try_from(b)
+                .ok()
+                .filter(|s| *s < i64::BITS)
+                .ok_or_else(|| format!("Invalid shift amount in {a} {} {b}", op.symbol()))?;
+            if op == Op::Shl {
+                a << shift
+            } else {
+                a >> shift
             }
         }
-        _ => Expanded::Str("Error".to_string()),
+        Op::BitNot => unreachable!("`~` is unary"),
+        Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => unreachable!("Compared above"),
+    };
+    Ok(Expanded::Int(result))
+}
+
+/// `[== a b]`, `[!= a b]`, `[< a b]`, `[<= a b]`, `[> a b]`, `[>= a b]`: `1` if the comparison
+/// holds, `0` otherwise. The operands compare like they do in `calc`.
+pub async fn comparison<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    op: &str,
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    let op = match op {
+        "==" => Op::Eq,
+        "!=" => Op::Ne,
+        "<" => Op::Lt,
+        "<=" => Op::Le,
+        ">" => Op::Gt,
+        ">=" => Op::Ge,
+        _ => unreachable!("Not a comparison: {op}"),
+    };
+    let [lhs, rhs] = &args[..] else {
+        return builtin_failed!(op.symbol(), "Expected exactly two arguments", args);
+    };
+    let (lhs, rhs) = (
+        operand_value(expand_value(op.symbol(), stk, ctx, lhs, args).await?),
+        operand_value(expand_value(op.symbol(), stk, ctx, rhs, args).await?),
+    );
+    match compare(op, &lhs, &rhs) {
+        Ok(res) => Ok(Expanded::Int(res as i64)),
+        Err(msg) => builtin_failed!(op.symbol(), msg, args),
+    }
+}
+
+/// Integers compare numerically, strings lexicographically. Values of different kinds are never
+/// equal and can't be ordered.
+fn compare(op: Op, lhs: &Expanded, rhs: &Expanded) -> Result<bool, String> {
+    let ordering = match (lhs, rhs) {
+        (Expanded::Int(a), Expanded::Int(b)) => a.cmp(b),
+        (Expanded::Str(a), Expanded::Str(b)) => a.cmp(b),
+        _ => match op {
+            Op::Eq => return Ok(false),
+            Op::Ne => return Ok(true),
+            _ => {
+                return Err(format!(
+                    "Cannot order {} and {} with `{}`",
+                    value_text(lhs),
+                    value_text(rhs),
+                    op.symbol()
+                ))
+            }
+        },
+    };
+    Ok(match op {
+        Op::Eq => ordering.is_eq(),
+        Op::Ne => ordering.is_ne(),
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Gt => ordering.is_gt(),
+        Op::Ge => ordering.is_ge(),
+        _ => unreachable!("Not a comparison: {op:?}"),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use CalcToken::{Close, Open};
+
+    fn int(n: i64) -> CalcToken {
+        CalcToken::Value(Expanded::Int(n))
+    }
+
+    fn op(op: Op) -> CalcToken {
+        CalcToken::Op(op)
+    }
+
+    fn eval(tokens: Vec<CalcToken>) -> Result<Expanded, String> {
+        Calculator { tokens, pos: 0 }.evaluate()
+    }
+
+    #[test]
+    fn precedence() {
+        // 2 + 3 * 4 ** 2 - -1
+        let tokens = vec![
+            int(2),
+            op(Op::Add),
+            int(3),
+            op(Op::Mul),
+            int(4),
+            op(Op::Pow),
+            int(2),
+            op(Op::Sub),
+            op(Op::Sub),
+            int(1),
+        ];
+        assert_eq!(eval(tokens), Ok(Expanded::Int(51)));
+
+        // (1 + 2) << 2 & 7
+        let tokens = vec![
+            Open,
+            int(1),
+            op(Op::Add),
+            int(2),
+            Close,
+            op(Op::Shl),
+            int(2),
+            op(Op::BitAnd),
+            int(7),
+        ];
+        assert_eq!(eval(tokens), Ok(Expanded::Int(4)));
+
+        // -2 ** 2, 2 ** 3 ** 2
+        assert_eq!(
+            eval(vec![op(Op::Sub), int(2), op(Op::Pow), int(2)]),
+            Ok(Expanded::Int(-4))
+        );
+        assert_eq!(
+            eval(vec![int(2), op(Op::Pow), int(3), op(Op::Pow), int(2)]),
+            Ok(Expanded::Int(512))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert!(eval(vec![int(1), op(Op::Div), int(0)]).is_err());
+        assert!(eval(vec![int(1), op(Op::Rem), int(0)]).is_err());
+        assert!(eval(vec![int(i64::MAX), op(Op::Add), int(1)]).is_err());
+        assert!(eval(vec![int(i64::MIN), op(Op::Div), op(Op::Sub), int(1)]).is_err());
+        assert!(eval(vec![int(2), op(Op::Pow), op(Op::Sub), int(1)]).is_err());
+        assert!(eval(vec![int(1), op(Op::Shl), int(64)]).is_err());
+        assert!(eval(vec![Open, int(1)]).is_err());
+        assert!(eval(vec![int(1), int(2)]).is_err());
+        assert!(eval(vec![]).is_err());
+
+        // Tokens are shown as they are written
+        assert_eq!(
+            eval(vec![int(1), Close]),
+            Err("Unexpected `)` after complete expression".to_string())
+        );
+        assert_eq!(
+            eval(vec![op(Op::Mul), int(1)]),
+            Err("Expected a value, found `*`".to_string())
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        // 1 + 1 == 2
+        let tokens = vec![int(1), op(Op::Add), int(1), op(Op::Eq), int(2)];
+        assert_eq!(eval(tokens), Ok(Expanded::Int(1)));
+
+        // 2 < 1 bor 1
+        let tokens = vec![int(2), op(Op::Lt), int(1), op(Op::BitOr), int(1)];
+        assert_eq!(eval(tokens), Ok(Expanded::Int(0)));
+
+        let str = |s: &str| CalcToken::Value(Expanded::Str(s.into()));
+        assert_eq!(
+            eval(vec![str("a"), op(Op::Lt), str("b")]),
+            Ok(Expanded::Int(1))
+        );
+        assert_eq!(
+            eval(vec![str("a"), op(Op::Ne), int(1)]),
+            Ok(Expanded::Int(1))
+        );
+        assert!(eval(vec![str("a"), op(Op::Lt), int(1)]).is_err());
+        assert!(eval(vec![int(1), op(Op::Lt), int(2), op(Op::Lt), int(3)]).is_err());
+    }
+
+    #[test]
+    fn string_operands() {
+        let tokens = vec![
+            CalcToken::Value(Expanded::Str("ab".into())),
+            op(Op::Mul),
+            int(3),
+        ];
+        assert!(eval(tokens).is_err());
+    }
+}
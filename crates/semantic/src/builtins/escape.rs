@@ -0,0 +1,602 @@
+//! Escaping for target languages: `[escape/html text]`, `[unescape/json text]`
+//!
+//! `json`, `c` and `python` escape the contents of a double quoted string literal, without the
+//! quotes. `shell` produces a complete single quoted word, `url` a percent-encoded URL component.
+
+use super::{apply, text};
+use crate::{context::EvaluationContext, expand::Expanded, expansion_error::ExpansionResult};
+use expandr_syntax::{
+    ast::mapping::Args,
+    source_type::{Owned, SourceType},
+};
+use reblessive::Stk;
+
+/// Names accepted by `Escaping::from_name`, for error messages
+pub const ESCAPING_MODES: &str = "html, html-attr, json, c, shell, python, url";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Escaping {
+    /// `&`, `<` and `>`
+    Html,
+    /// Like `Html`, also escapes quotes
+    HtmlAttr,
+    Json,
+    C,
+    Shell,
+    Python,
+    Url,
+}
+
+impl Escaping {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "html" => Self::Html,
+            "html-attr" => Self::HtmlAttr,
+            "json" => Self::Json,
+            "c" => Self::C,
+            "shell" => Self::Shell,
+            "python" => Self::Python,
+            "url" => Self::Url,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::HtmlAttr => "html-attr",
+            Self::Json => "json",
+            Self::C => "c",
+            Self::Shell => "shell",
+            Self::Python => "python",
+            Self::Url => "url",
+        }
+    }
+
+    pub fn escape(self, s: &str) -> String {
+        match self {
+            Self::Html => escape_html(s, false),
+            Self::HtmlAttr => escape_html(s, true),
+            Self::Json => escape_json(s),
+            Self::C => escape_c(s),
+            Self::Shell => format!("'{}'", s.replace('\'', r"'\''")),
+            Self::Python => escape_python(s),
+            Self::Url => escape_url(s),
+        }
+    }
+
+    /// Whether text escaped like `self` is also correctly escaped for `mode`
+    pub fn satisfies(self, mode: Self) -> bool {
+        self == mode || (self, mode) == (Self::HtmlAttr, Self::Html)
+    }
+
+    /// Escape a value interpolated in autoescape mode, safe text is kept as is
+    pub fn interpolate(self, value: Expanded) -> String {
+        match value {
+            Expanded::Safe(s) => s,
+            value => self.escape(&value.into_string()),
+        }
+    }
+
+    pub fn unescape(self, s: &str) -> Result<String, String> {
+        match self {
+            Self::Html | Self::HtmlAttr => Ok(unescape_html(s)),
+            Self::Json => unescape_json(s),
+            Self::C => unescape_c(s),
+            Self::Shell => unescape_shell(s),
+            Self::Python => unescape_python(s),
+            Self::Url => unescape_url(s),
+        }
+    }
+}
+
+macro_rules! escape_builtins {
+    ($($escape:ident, $unescape:ident => $variant:ident),* $(,)?) => {$(
+        pub async fn $escape<S: SourceType, Ctx: EvaluationContext<Owned>>(
+            stk: &mut Stk,
+            ctx: &Ctx,
+            args: &Args<S>,
+        ) -> ExpansionResult {
+            let escaping = Escaping::$variant;
+            let builtin = format!("escape/{}", escaping.name());
+            // Don't escape twice in autoescape mode
+            let safe = ctx.options().autoescape.is_some_and(|mode| escaping.satisfies(mode));
+            apply(&builtin, stk, ctx, args, 1..=1, |v| {
+                let escaped = escaping.escape(&text(&v[0]));
+                Ok(if safe { Expanded::Safe(escaped) } else { Expanded::Str(escaped) })
+            }).await
+        }
+
+        pub async fn $unescape<S: SourceType, Ctx: EvaluationContext<Owned>>(
+            stk: &mut Stk,
+            ctx: &Ctx,
+            args: &Args<S>,
+        ) -> ExpansionResult {
+            let escaping = Escaping::$variant;
+            let builtin = format!("unescape/{}", escaping.name());
+            apply(&builtin, stk, ctx, args, 1..=1, |v| {
+                escaping.unescape(&text(&v[0])).map(Expanded::Str)
+            }).await
+        }
+    )*};
+}
+
+escape_builtins! {
+    html, unescape_html_builtin => Html,
+    html_attr, unescape_html_attr_builtin => HtmlAttr,
+    json, unescape_json_builtin => Json,
+    c, unescape_c_builtin => C,
+    shell, unescape_shell_builtin => Shell,
+    python, unescape_python_builtin => Python,
+    url, unescape_url_builtin => Url,
+}
+
+/// `[raw expr]`, `[safe expr]`: Opt out of autoescaping
+pub async fn raw<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("raw", stk, ctx, args, 1..=1, |v| {
+        Ok(Expanded::Safe(text(&v[0])))
+    })
+    .await
+}
+
+// Html
+
+fn escape_html(s: &str, quotes: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if quotes => out.push_str("&quot;"),
+            '\'' if quotes => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Length of the longest entity name `unescape_html` decodes, `#x10FFFF` or `#1114111`
+const MAX_ENTITY_LEN: usize = 8;
+
+/// Named entities of `escape_html` and numeric ones, anything else is kept as is
+fn unescape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        // Only look as far as the longest entity could reach, rescanning the whole rest from
+        // every `&` would be quadratic
+        let window = &rest.as_bytes()[1..rest.len().min(MAX_ENTITY_LEN + 2)];
+        let entity = window
+            .iter()
+            .position(|&b| b == b';')
+            .map(|end| &rest[1..end + 1])
+            .and_then(|name| Some((name, html_entity(name)?)));
+        match entity {
+            Some((name, c)) => {
+                out.push(c);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn html_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let num = name.strip_prefix('#')?;
+            let code = match num.strip_prefix(['x', 'X']) {
+                Some(hex) => parse_hex(hex)?,
+                None if num.bytes().all(|b| b.is_ascii_digit()) => num.parse().ok()?,
+                None => return None,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+// Json
+
+fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape_json(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some('/') => out.push('/'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('u') => {
+                let high = hex_digits(&mut chars, 4)?;
+                let code = match high {
+                    // Surrogate pair
+                    0xD800..=0xDBFF => {
+                        if chars.next() != Some('\\') || chars.next() != Some('u') {
+                            return Err("Unpaired surrogate in \\u escape".to_string());
+                        }
+                        let low = hex_digits(&mut chars, 4)?;
+                        if !(0xDC00..=0xDFFF).contains(&low) {
+                            return Err("Unpaired surrogate in \\u escape".to_string());
+                        }
+                        0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                    }
+                    _ => high,
+                };
+                out.push(char_from(code)?);
+            }
+            Some(c) => return Err(format!("Invalid escape sequence `\\{c}`")),
+            None => return Err("Trailing `\\`".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+// C
+
+fn escape_c(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            // Trigraphs
+            b'?' => out.push_str("\\?"),
+            // Octal escapes take at most three digits, unlike hex escapes, which would swallow
+            // following hex digits
+            b if !(b' '..=b'~').contains(&b) => out.push_str(&format!("\\{b:03o}")),
+            b => out.push(b as char),
+        }
+    }
+    out
+}
+
+fn unescape_c(s: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        let byte = match chars.next() {
+            Some('a') => 0x07,
+            Some('b') => 0x08,
+            Some('f') => 0x0C,
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('v') => 0x0B,
+            Some(c @ ('\\' | '\'' | '"' | '?')) => c as u8,
+            Some(d @ '0'..='7') => {
+                let mut value = d.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                u8::try_from(value).map_err(|_| format!("Octal escape `\\{value:o}` too large"))?
+            }
+            Some('x') => {
+                let mut value: u32 = 0;
+                let mut digits = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) {
+                    value = value.saturating_mul(16).saturating_add(digit);
+                    digits += 1;
+                    chars.next();
+                }
+                if digits == 0 {
+                    return Err("Missing hex digits after `\\x`".to_string());
+                }
+                u8::try_from(value).map_err(|_| "Hex escape out of range".to_string())?
+            }
+            Some(u @ ('u' | 'U')) => {
+                let code = hex_digits(&mut chars, if u == 'u' { 4 } else { 8 })?;
+                let c = char_from(code)?;
+                bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+            Some(c) => return Err(format!("Invalid escape sequence `\\{c}`")),
+            None => return Err("Trailing `\\`".to_string()),
+        };
+        bytes.push(byte);
+    }
+    String::from_utf8(bytes).map_err(|_| "Escaped bytes are not valid UTF-8".to_string())
+}
+
+// Shell
+
+/// Undo single, double and backslash quoting of a single shell word
+fn unescape_shell(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => loop {
+                match chars.next() {
+                    Some('\'') => break,
+                    Some(c) => out.push(c),
+                    None => return Err("Unterminated `'`".to_string()),
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ ('"' | '\\' | '$' | '`')) => out.push(c),
+                        Some('\n') => {}
+                        Some(c) => {
+                            out.push('\\');
+                            out.push(c);
+                        }
+                        None => return Err("Unterminated `\"`".to_string()),
+                    },
+                    Some(c) => out.push(c),
+                    None => return Err("Unterminated `\"`".to_string()),
+                }
+            },
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => out.push(c),
+                None => return Err("Trailing `\\`".to_string()),
+            },
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
+
+// Python
+
+fn escape_python(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\'' => out.push_str("\\'"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => match c as u32 {
+                code @ 0..=0xFF => out.push_str(&format!("\\x{code:02x}")),
+                code => out.push_str(&format!("\\u{code:04x}")),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape_python(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\n') => {}
+            Some(c @ ('\\' | '\'' | '"')) => out.push(c),
+            Some('a') => out.push('\u{7}'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('v') => out.push('\u{b}'),
+            Some(d @ '0'..='7') => {
+                let mut value = d.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                out.push(char_from(value)?);
+            }
+            Some('x') => out.push(char_from(hex_digits(&mut chars, 2)?)?),
+            Some('u') => out.push(char_from(hex_digits(&mut chars, 4)?)?),
+            Some('U') => out.push(char_from(hex_digits(&mut chars, 8)?)?),
+            Some('N') => {
+                return Err("Named unicode escapes (`\\N{..}`) are not supported".to_string())
+            }
+            // Unknown escapes are kept in python
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            None => return Err("Trailing `\\`".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+// Url
+
+fn escape_url(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    out
+}
+
+fn unescape_url(s: &str) -> Result<String, String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(parse_hex)
+            .ok_or("Expected two hex digits after `%`")?;
+        bytes.push(hex as u8);
+        rest = &rest[2..];
+    }
+    String::from_utf8(bytes).map_err(|_| "Decoded bytes are not valid UTF-8".to_string())
+}
+
+// Helpers
+
+fn hex_digits(chars: &mut impl Iterator<Item = char>, n: usize) -> Result<u32, String> {
+    let digits: String = chars.take(n).collect();
+    if digits.len() != n {
+        return Err(format!("Expected {n} hex digits, found {digits:?}"));
+    }
+    parse_hex(&digits).ok_or_else(|| format!("Expected {n} hex digits, found {digits:?}"))
+}
+
+/// Unlike `from_str_radix`, this doesn't accept a leading sign
+fn parse_hex(digits: &str) -> Option<u32> {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+fn char_from(code: u32) -> Result<char, String> {
+    char::from_u32(code).ok_or_else(|| format!("Invalid code point {code:#x}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Escaping; 7] = [
+        Escaping::Html,
+        Escaping::HtmlAttr,
+        Escaping::Json,
+        Escaping::C,
+        Escaping::Shell,
+        Escaping::Python,
+        Escaping::Url,
+    ];
+
+    #[test]
+    fn round_trip() {
+        let inputs = [
+            "",
+            "plain",
+            r#"<a href="x">Tom & 'Jerry'</a>"#,
+            "tab\tnew\nline\r\0 \u{1b}[0m",
+            r"back\slash ?? %41 $HOME `cmd`",
+            "ünïcödé 🦀",
+        ];
+        for escaping in ALL {
+            for input in inputs {
+                let escaped = escaping.escape(input);
+                assert_eq!(
+                    escaping.unescape(&escaped).as_deref(),
+                    Ok(input),
+                    "{escaping:?} of {input:?} via {escaped:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn escaped() {
+        assert_eq!(
+            Escaping::Html.escape("<b>'&'</b>"),
+            "&lt;b&gt;'&amp;'&lt;/b&gt;"
+        );
+        assert_eq!(Escaping::HtmlAttr.escape(r#"a"b'"#), "a&quot;b&#39;");
+        assert_eq!(Escaping::Json.escape("\"\u{1}"), r#"\"\u0001"#);
+        assert_eq!(Escaping::C.escape("é1"), r"\303\2511");
+        assert_eq!(Escaping::Shell.escape("it's"), r"'it'\''s'");
+        assert_eq!(Escaping::Python.escape("'\u{7f}"), r"\'\x7f");
+        assert_eq!(Escaping::Url.escape("a b/ü"), "a%20b%2F%C3%BC");
+    }
+
+    #[test]
+    fn unescaped() {
+        assert_eq!(
+            unescape_html("&lt;&#x41;&#66;&unknown; & x"),
+            "<AB&unknown; & x"
+        );
+        assert_eq!(unescape_json(r"\ud83e\udd80\/"), Ok("🦀/".to_string()));
+        assert_eq!(unescape_c(r"\x41\101é"), Ok("AAé".to_string()));
+        assert_eq!(
+            unescape_shell(r#"'a b'\ "c\"d""#),
+            Ok(r#"a b c"d"#.to_string())
+        );
+        assert_eq!(unescape_python(r"\d\x41"), Ok(r"\dA".to_string()));
+
+        assert!(unescape_json(r"\q").is_err());
+        assert!(unescape_json(r"\ud83e").is_err());
+        assert!(unescape_c(r"\777").is_err());
+        assert!(unescape_shell("'open").is_err());
+        assert!(unescape_url("%4").is_err());
+        assert!(unescape_url("%ff").is_err());
+        assert!(unescape_url("%+1").is_err());
+        assert!(unescape_json(r"\u+041").is_err());
+        assert_eq!(unescape_html("&#+65;&#x+41;"), "&#+65;&#x+41;");
+        assert_eq!(unescape_html("&&amp;&&#x10FFFF;"), "&&&\u{10ffff}");
+    }
+}
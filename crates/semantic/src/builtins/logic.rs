@@ -0,0 +1,73 @@
+use super::expand_value;
+use crate::{
+    builtin_failed, context::EvaluationContext, expand::Expanded, expansion_error::ExpansionResult,
+};
+use expandr_syntax::{
+    ast::mapping::Args,
+    source_type::{Owned, SourceType},
+};
+use reblessive::Stk;
+
+/// `[if cond then]`, `[if cond then else]`. Only the chosen branch is expanded, a missing else
+/// branch expands to nothing.
+pub async fn if_expr<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    let (cond, then, otherwise) = match &args[..] {
+        [cond, then] => (cond, then, None),
+        [cond, then, otherwise] => (cond, then, Some(otherwise)),
+        _ => builtin_failed!("if", "Expected a condition and one or two branches", args)?,
+    };
+
+    if expand_value("if", stk, ctx, cond, args).await?.is_truthy() {
+        expand_value("if", stk, ctx, then, args).await
+    } else {
+        match otherwise {
+            Some(otherwise) => expand_value("if", stk, ctx, otherwise, args).await,
+            None => Ok(Expanded::Str(String::new())),
+        }
+    }
+}
+
+/// `1` if all arguments are true. Stops at the first false one.
+pub async fn and<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    for arg in args {
+        if !expand_value("and", stk, ctx, arg, args).await?.is_truthy() {
+            return Ok(Expanded::Int(0));
+        }
+    }
+    Ok(Expanded::Int(1))
+}
+
+/// `1` if any argument is true. Stops at the first true one.
+pub async fn or<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    for arg in args {
+        if expand_value("or", stk, ctx, arg, args).await?.is_truthy() {
+            return Ok(Expanded::Int(1));
+        }
+    }
+    Ok(Expanded::Int(0))
+}
+
+pub async fn not<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    let [arg] = &args[..] else {
+        builtin_failed!("not", "Expected exactly one argument", args)?
+    };
+    Ok(Expanded::Int(
+        !expand_value("not", stk, ctx, arg, args).await?.is_truthy() as i64,
+    ))
+}
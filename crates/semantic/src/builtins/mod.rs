@@ -1,21 +1,235 @@
+use std::ops::RangeInclusive;
+
 use expandr_syntax::{
-    ast::mapping::Args,
+    ast::{mapping::Args, Expr},
     source_type::{Owned, SourceType},
 };
+use reblessive::Stk;
 
-use crate::{context::EvaluationContext, expansion_error::ExpansionResult};
+use crate::{
+    builtin_failed,
+    context::EvaluationContext,
+    expand::{Expandable as _, Expanded},
+    expansion_error::ExpansionResult,
+};
 
 mod calculate;
-mod is_expr;
+pub(crate) use calculate::evaluate_math;
+mod escape;
+pub use escape::{Escaping, ESCAPING_MODES};
+mod logic;
+mod reindent;
+mod string;
 
-type BuiltinFn<S, Ctx> = fn(&Ctx, &Args<S>) -> ExpansionResult;
-
-pub fn get_builtin<S: SourceType, Ctx: EvaluationContext<Owned>>(
+/// Apply the builtin `name` to `args`, `None` if there is no such builtin
+pub async fn apply_builtin<S: SourceType, Ctx: EvaluationContext<Owned>>(
     name: &str,
-) -> Option<BuiltinFn<S, Ctx>> {
-    match name {
-        "calc" => Some(calculate::evaluate_math),
-        "is" => Some(is_expr::is_expr),
-        &_ => None,
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> Option<ExpansionResult> {
+    Some(match name {
+        "calc" => calculate::evaluate_math(stk, ctx, args).await,
+        "if" => logic::if_expr(stk, ctx, args).await,
+        "and" => logic::and(stk, ctx, args).await,
+        "or" => logic::or(stk, ctx, args).await,
+        "not" => logic::not(stk, ctx, args).await,
+        "==" => calculate::comparison("==", stk, ctx, args).await,
+        "!=" => calculate::comparison("!=", stk, ctx, args).await,
+        "<" => calculate::comparison("<", stk, ctx, args).await,
+        "<=" => calculate::comparison("<=", stk, ctx, args).await,
+        ">" => calculate::comparison(">", stk, ctx, args).await,
+        ">=" => calculate::comparison(">=", stk, ctx, args).await,
+        "reindent" => reindent::reindent(stk, ctx, args).await,
+        "no-reindent" => reindent::no_reindent(stk, ctx, args).await,
+
+        "str/snake" => string::snake(stk, ctx, args).await,
+        "str/camel" => string::camel(stk, ctx, args).await,
+        "str/pascal" => string::pascal(stk, ctx, args).await,
+        "str/kebab" => string::kebab(stk, ctx, args).await,
+        "str/screaming" => string::screaming(stk, ctx, args).await,
+        "str/upper" => string::upper(stk, ctx, args).await,
+        "str/lower" => string::lower(stk, ctx, args).await,
+        "str/trim" => string::trim(stk, ctx, args).await,
+        "str/replace" => string::replace(stk, ctx, args).await,
+        "str/split" => string::split(stk, ctx, args).await,
+        "str/substr" => string::substr(stk, ctx, args).await,
+        "str/starts-with" => string::starts_with(stk, ctx, args).await,
+        "str/ends-with" => string::ends_with(stk, ctx, args).await,
+        "str/len" => string::len(stk, ctx, args).await,
+        "str/pad-left" => string::pad_left(stk, ctx, args).await,
+        "str/pad-right" => string::pad_right(stk, ctx, args).await,
+        "str/repeat" => string::repeat(stk, ctx, args).await,
+
+        "raw" | "safe" => escape::raw(stk, ctx, args).await,
+        "escape/html" => escape::html(stk, ctx, args).await,
+        "escape/html-attr" => escape::html_attr(stk, ctx, args).await,
+        "escape/json" => escape::json(stk, ctx, args).await,
+        "escape/c" => escape::c(stk, ctx, args).await,
+        "escape/shell" => escape::shell(stk, ctx, args).await,
+        "escape/python" => escape::python(stk, ctx, args).await,
+        "escape/url" => escape::url(stk, ctx, args).await,
+        "unescape/html" => escape::unescape_html_builtin(stk, ctx, args).await,
+        "unescape/html-attr" => escape::unescape_html_attr_builtin(stk, ctx, args).await,
+        "unescape/json" => escape::unescape_json_builtin(stk, ctx, args).await,
+        "unescape/c" => escape::unescape_c_builtin(stk, ctx, args).await,
+        "unescape/shell" => escape::unescape_shell_builtin(stk, ctx, args).await,
+        "unescape/python" => escape::unescape_python_builtin(stk, ctx, args).await,
+        "unescape/url" => escape::unescape_url_builtin(stk, ctx, args).await,
+        _ => return None,
+    })
+}
+
+/// Builtins that only depend on their arguments. Results that applied a builtin which isn't listed
+/// here are not memoized, so a new builtin is only cached once it is added.
+const PURE_BUILTINS: &[&str] = &[
+    "calc",
+    "if",
+    "and",
+    "or",
+    "not",
+    "==",
+    "!=",
+    "<",
+    "<=",
+    ">",
+    ">=",
+    "reindent",
+    "no-reindent",
+    "str/snake",
+    "str/camel",
+    "str/pascal",
+    "str/kebab",
+    "str/screaming",
+    "str/upper",
+    "str/lower",
+    "str/trim",
+    "str/replace",
+    "str/split",
+    "str/substr",
+    "str/starts-with",
+    "str/ends-with",
+    "str/len",
+    "str/pad-left",
+    "str/pad-right",
+    "str/repeat",
+    "raw",
+    "safe",
+    "escape/html",
+    "escape/html-attr",
+    "escape/json",
+    "escape/c",
+    "escape/shell",
+    "escape/python",
+    "escape/url",
+    "unescape/html",
+    "unescape/html-attr",
+    "unescape/json",
+    "unescape/c",
+    "unescape/shell",
+    "unescape/python",
+    "unescape/url",
+];
+
+/// Whether the builtin `name` only depends on its arguments
+pub fn is_pure(name: &str) -> bool {
+    PURE_BUILTINS.contains(&name)
+}
+
+type StrResult = Result<Expanded, String>;
+
+/// Expand all arguments, check their count and run `f` on them
+async fn apply<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    builtin: &str,
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+    arity: RangeInclusive<usize>,
+    f: impl FnOnce(&[Expanded]) -> StrResult,
+) -> ExpansionResult {
+    if !arity.contains(&args.len()) {
+        let msg = match (arity.start(), arity.end()) {
+            (start, end) if start == end => format!("Expected {start} argument(s)"),
+            (start, end) => format!("Expected {start} to {end} arguments"),
+        };
+        return builtin_failed!(builtin, msg, args);
+    }
+
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        values.push(expand_value(builtin, stk, ctx, arg, args).await?);
+    }
+
+    match f(&values) {
+        Ok(value) => Ok(value),
+        Err(msg) => builtin_failed!(builtin, msg, args),
+    }
+}
+
+/// Expand a single argument. Bare symbols and operators are not values and can't be expanded.
+async fn expand_value<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    builtin: &str,
+    stk: &mut Stk,
+    ctx: &Ctx,
+    arg: &Expr<S>,
+    args: &Args<S>,
+) -> ExpansionResult {
+    match arg {
+        Expr::LiteralSymbol(_) | Expr::LiteralOperator(_) => {
+            builtin_failed!(builtin, "Expected a value, found a symbol", args)
+        }
+        _ => arg.expand_on(stk, ctx).await,
+    }
+}
+
+fn text(value: &Expanded) -> String {
+    value.clone().into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use expandr_syntax::{ast::mapping::Args, source_type::Owned, ProgramContext};
+    use reblessive::Stack;
+
+    use super::{apply_builtin, is_pure, PURE_BUILTINS};
+    use crate::{
+        context::get_owned_context, expand::Expandable as _, expansion_error::ExpansionError,
+    };
+
+    /// Builtins are only cached if they are listed as pure, the list must not name builtins that
+    /// don't exist
+    #[test]
+    fn pure_builtins() {
+        let ctx = ProgramContext::<Owned>::default();
+        let no_args: Args<Owned> = Vec::new();
+        for name in PURE_BUILTINS {
+            let applied = Stack::new()
+                .enter(|stk| apply_builtin(name, stk, &ctx, &no_args))
+                .finish();
+            assert!(applied.is_some(), "{name}");
+        }
+        assert!(!is_pure("time/now"));
+    }
+
+    /// Symbols are rejected as arguments of builtins that expand their arguments to values
+    #[test]
+    fn symbol_arguments() {
+        let applications = [
+            "[str/len ,]",
+            "[escape/html ,]",
+            "[if + 'a' 'b']",
+            "[and 1 ,]",
+            "[not -]",
+            "[== - 1]",
+        ];
+        for application in applications {
+            let src = format!("'''{application}'''");
+            let ast = expandr_syntax::parse(&src, None).unwrap();
+            let ctx = get_owned_context(ast.ctx.clone());
+            assert!(
+                matches!(ast.expand(&ctx), Err(ExpansionError::BuiltinFailed { .. })),
+                "{application}"
+            );
+        }
     }
 }
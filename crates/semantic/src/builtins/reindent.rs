@@ -0,0 +1,50 @@
+use crate::{
+    builtin_failed,
+    context::{EvaluationContext, ExpansionOptions, OptionsContext},
+    expand::Expandable as _,
+    expansion_error::ExpansionResult,
+};
+use expandr_syntax::{
+    ast::mapping::Args,
+    source_type::{Owned, SourceType},
+};
+use reblessive::Stk;
+
+/// `[reindent expr]`: Expand `expr` with continuation lines of interpolated values indented to the
+/// column they are interpolated at
+pub async fn reindent<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    with_reindent("reindent", true, stk, ctx, args).await
+}
+
+/// `[no-reindent expr]`: Expand `expr` without reindenting, e.g. if it is enabled globally
+pub async fn no_reindent<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    with_reindent("no-reindent", false, stk, ctx, args).await
+}
+
+async fn with_reindent<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    builtin: &str,
+    reindent: bool,
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    let [arg] = &args[..] else {
+        builtin_failed!(builtin, "Expected exactly one argument", args)?
+    };
+    let scope = OptionsContext {
+        inner: ctx,
+        options: ExpansionOptions {
+            reindent,
+            ..ctx.options()
+        },
+    };
+    arg.expand_on(stk, &scope).await
+}
@@ -0,0 +1,355 @@
+//! String builtins, all namespaced under `str/`: `[str/snake 'HttpServer']`
+
+use super::{apply, text, StrResult};
+use crate::{context::EvaluationContext, expand::Expanded, expansion_error::ExpansionResult};
+use expandr_syntax::{
+    ast::mapping::Args,
+    source_type::{Owned, SourceType},
+};
+use reblessive::Stk;
+
+fn int(value: &Expanded, what: &str) -> Result<i64, String> {
+    match value {
+        Expanded::Int(i) => Ok(*i),
+        Expanded::Str(s) | Expanded::Safe(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| format!("Expected an integer as {what}, found {s:?}")),
+    }
+}
+
+fn count(value: &Expanded, what: &str) -> Result<usize, String> {
+    let i = int(value, what)?;
+    usize::try_from(i).map_err(|_| format!("Expected a non-negative {what}, found {i}"))
+}
+
+fn fill_char(value: Option<&Expanded>) -> Result<char, String> {
+    let Some(value) = value else {
+        return Ok(' ');
+    };
+    let fill = text(value);
+    let mut chars = fill.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("Expected a single fill character, found {fill:?}")),
+    }
+}
+
+fn bool_int(b: bool) -> Expanded {
+    Expanded::Int(b as i64)
+}
+
+/// Split an identifier into lowercase words, at `_`, `-`, `.`, whitespace and case changes.
+/// Acronyms stay together: `HTTPServer` -> `http server`
+fn words(s: &str) -> Vec<String> {
+    let chars: Vec<char> = s.chars().collect();
+    let mut words = Vec::new();
+    let mut current = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' || c == '-' || c == '.' || c.is_whitespace() {
+            if !current.is_empty() {
+                words.push(std::mem::take(&mut current));
+            }
+            continue;
+        }
+        if c.is_uppercase() && !current.is_empty() {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_is_lower) {
+                words.push(std::mem::take(&mut current));
+            }
+        }
+        current.extend(c.to_lowercase());
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn convert_case(s: &str, case: &str) -> String {
+    let words = words(s);
+    match case {
+        "snake" => words.join("_"),
+        "kebab" => words.join("-"),
+        "screaming" => words.join("_").to_uppercase(),
+        "pascal" => words.iter().map(|w| capitalize(w)).collect(),
+        "camel" => words
+            .iter()
+            .enumerate()
+            .map(|(i, w)| if i == 0 { w.clone() } else { capitalize(w) })
+            .collect(),
+        _ => unreachable!("Unknown case {case}"),
+    }
+}
+
+macro_rules! case_builtin {
+    ($fn_name:ident, $case:literal) => {
+        #[doc = concat!("`[str/", $case, " text]`")]
+        pub async fn $fn_name<S: SourceType, Ctx: EvaluationContext<Owned>>(
+            stk: &mut Stk,
+            ctx: &Ctx,
+            args: &Args<S>,
+        ) -> ExpansionResult {
+            apply(concat!("str/", $case), stk, ctx, args, 1..=1, |v| {
+                Ok(Expanded::Str(convert_case(&text(&v[0]), $case)))
+            })
+            .await
+        }
+    };
+}
+
+case_builtin!(snake, "snake");
+case_builtin!(camel, "camel");
+case_builtin!(pascal, "pascal");
+case_builtin!(kebab, "kebab");
+case_builtin!(screaming, "screaming");
+
+/// `[str/upper text]`
+pub async fn upper<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/upper", stk, ctx, args, 1..=1, |v| {
+        Ok(Expanded::Str(text(&v[0]).to_uppercase()))
+    })
+    .await
+}
+
+/// `[str/lower text]`
+pub async fn lower<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/lower", stk, ctx, args, 1..=1, |v| {
+        Ok(Expanded::Str(text(&v[0]).to_lowercase()))
+    })
+    .await
+}
+
+/// `[str/trim text]`, removes leading and trailing whitespace
+pub async fn trim<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/trim", stk, ctx, args, 1..=1, |v| {
+        Ok(Expanded::Str(text(&v[0]).trim().to_string()))
+    })
+    .await
+}
+
+/// `[str/replace text from to]`, replaces all occurrences
+pub async fn replace<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/replace", stk, ctx, args, 3..=3, |v| {
+        let from = text(&v[1]);
+        if from.is_empty() {
+            return Err("Cannot replace the empty string".to_string());
+        }
+        Ok(Expanded::Str(text(&v[0]).replace(&from, &text(&v[2]))))
+    })
+    .await
+}
+
+/// `[str/split text sep index]`, the `index`th part of `text` split at `sep`. Negative indices count
+/// from the end.
+pub async fn split<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/split", stk, ctx, args, 3..=3, |v| {
+        let (s, sep) = (text(&v[0]), text(&v[1]));
+        if sep.is_empty() {
+            return Err("Cannot split at the empty string".to_string());
+        }
+        let parts: Vec<&str> = s.split(&sep).collect();
+        let index = int(&v[2], "index")?;
+        let resolved = match index {
+            0.. => usize::try_from(index).ok(),
+            _ => parts.len().checked_sub(index.unsigned_abs() as usize),
+        };
+        match resolved.and_then(|i| parts.get(i)) {
+            Some(part) => Ok(Expanded::Str(part.to_string())),
+            None => Err(format!(
+                "Index {index} out of range for {} parts",
+                parts.len()
+            )),
+        }
+    })
+    .await
+}
+
+/// `[str/substr text start]`, `[str/substr text start len]`, counted in chars
+pub async fn substr<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/substr", stk, ctx, args, 2..=3, |v| {
+        let s = text(&v[0]);
+        let total = s.chars().count();
+        let start = count(&v[1], "start")?;
+        let len = match v.get(2) {
+            Some(len) => count(len, "length")?,
+            None => total.saturating_sub(start),
+        };
+        if start.checked_add(len).is_none_or(|end| end > total) {
+            return Err(format!(
+                "Range {start}..{} out of bounds for {total} chars",
+                start.saturating_add(len)
+            ));
+        }
+        Ok(Expanded::Str(s.chars().skip(start).take(len).collect()))
+    })
+    .await
+}
+
+/// `[str/starts-with text prefix]`, `1` or `0`
+pub async fn starts_with<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/starts-with", stk, ctx, args, 2..=2, |v| {
+        Ok(bool_int(text(&v[0]).starts_with(&text(&v[1]))))
+    })
+    .await
+}
+
+/// `[str/ends-with text suffix]`, `1` or `0`
+pub async fn ends_with<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/ends-with", stk, ctx, args, 2..=2, |v| {
+        Ok(bool_int(text(&v[0]).ends_with(&text(&v[1]))))
+    })
+    .await
+}
+
+/// `[str/len text]`, in chars
+pub async fn len<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/len", stk, ctx, args, 1..=1, |v| {
+        Ok(Expanded::Int(text(&v[0]).chars().count() as i64))
+    })
+    .await
+}
+
+/// Longest string a builtin builds by repetition. Anything longer is a mistake, and allocating it
+/// would abort the process.
+const MAX_REPEATED_LEN: usize = 1 << 30;
+
+/// `s` repeated `n` times, an error instead if the result would exceed `MAX_REPEATED_LEN`
+fn repeat_str(s: &str, n: usize) -> Result<String, String> {
+    match s.len().checked_mul(n) {
+        Some(len) if len <= MAX_REPEATED_LEN => Ok(s.repeat(n)),
+        _ => Err(format!(
+            "Repeating {s:?} {n} times exceeds the maximum length of {MAX_REPEATED_LEN} bytes"
+        )),
+    }
+}
+
+fn pad(v: &[Expanded], left: bool) -> StrResult {
+    let s = text(&v[0]);
+    let width = count(&v[1], "width")?;
+    let fill = fill_char(v.get(2))?;
+    let padding = repeat_str(
+        fill.encode_utf8(&mut [0; 4]),
+        width.saturating_sub(s.chars().count()),
+    )?;
+    Ok(Expanded::Str(if left {
+        padding + &s
+    } else {
+        s + &padding
+    }))
+}
+
+/// `[str/pad-left text width]`, `[str/pad-left text width fill]`, fills with spaces by default
+pub async fn pad_left<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/pad-left", stk, ctx, args, 2..=3, |v| pad(v, true)).await
+}
+
+/// `[str/pad-right text width]`, `[str/pad-right text width fill]`
+pub async fn pad_right<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/pad-right", stk, ctx, args, 2..=3, |v| pad(v, false)).await
+}
+
+/// `[str/repeat text n]`
+pub async fn repeat<S: SourceType, Ctx: EvaluationContext<Owned>>(
+    stk: &mut Stk,
+    ctx: &Ctx,
+    args: &Args<S>,
+) -> ExpansionResult {
+    apply("str/repeat", stk, ctx, args, 2..=2, repeat_text).await
+}
+
+fn repeat_text(v: &[Expanded]) -> StrResult {
+    repeat_str(&text(&v[0]), count(&v[1], "count")?).map(Expanded::Str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cases() {
+        assert_eq!(
+            words("HTTPServer_v2-config"),
+            ["http", "server", "v2", "config"]
+        );
+        assert_eq!(words("parseJSONValue"), ["parse", "json", "value"]);
+        assert_eq!(convert_case("some-name", "pascal"), "SomeName");
+        assert_eq!(convert_case("SomeName", "camel"), "someName");
+        assert_eq!(convert_case("someName", "screaming"), "SOME_NAME");
+        assert_eq!(convert_case("Some Name", "kebab"), "some-name");
+        assert_eq!(convert_case("", "snake"), "");
+    }
+
+    #[test]
+    fn padding() {
+        let s = |s: &str| Expanded::Str(s.to_string());
+        assert_eq!(pad(&[s("7"), Expanded::Int(3), s("0")], true), Ok(s("007")));
+        assert_eq!(pad(&[s("long"), Expanded::Int(2)], false), Ok(s("long")));
+        assert!(pad(&[s("x"), Expanded::Int(3), s("ab")], true).is_err());
+        assert!(pad(&[s("x"), Expanded::Int(-1)], true).is_err());
+        assert!(pad(&[s("x"), Expanded::Int(i64::MAX)], true).is_err());
+    }
+
+    #[test]
+    fn repetition() {
+        let s = |s: &str| Expanded::Str(s.to_string());
+        assert_eq!(repeat_text(&[s("ab"), Expanded::Int(3)]), Ok(s("ababab")));
+        assert_eq!(repeat_text(&[s("ab"), Expanded::Int(0)]), Ok(s("")));
+        assert!(repeat_text(&[s("ab"), Expanded::Int(-1)]).is_err());
+        assert!(repeat_text(&[s("ab"), Expanded::Int(i64::MAX)]).is_err());
+    }
+}
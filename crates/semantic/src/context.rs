@@ -1,25 +1,160 @@
 use std::collections::HashMap;
 
+use crate::{
+    builtins::Escaping, expansion_error::ExpansionError, mapping_lookup::ArgValues, memo::MemoCache,
+};
 use expandr_syntax::{
     ast::{mapping::Mapping, Expr, PathIdent},
     source_type::{Borrowed, Owned, SourceType},
-    ProgramContext,
+    IntoOwned as _, ProgramContext, Symbol,
 };
 
+/// A mapping together with the scope it was defined in
+pub type LookupResult<'a, S> =
+    Result<Option<(&'a Mapping<S>, &'a dyn EvaluationContext<S>)>, ExpansionError>;
+
 /// General trait for global and local contexts / scopes
 pub trait EvaluationContext<S: SourceType> {
     /// Look up a mapping, identified by it's name and arguments. First checks in the current
-    /// scope, then its parent scope, then its parent scope and so on.
-    fn lookup(&self, name: &PathIdent, args: &[Expr<Owned>]) -> Option<&Mapping<S>>;
+    /// scope, then its parent scope, then its parent scope and so on. Fails if several
+    /// overloads match equally well, or if none matches only because of parameter types.
+    ///
+    /// Also returns the scope the mapping was defined in. Names in its translation are resolved
+    /// there and not where it is applied, so a mapping can't see the parameters of its callers.
+    fn lookup<'a>(&'a self, name: &PathIdent, args: &ArgValues<'a>) -> LookupResult<'a, S>;
+
+    /// Look up an unevaluated argument bound to a lazy parameter, together with the scope it has
+    /// to be expanded in.
+    fn lookup_lazy(&self, _name: &PathIdent) -> Option<(&Expr<S>, &dyn EvaluationContext<S>)> {
+        None
+    }
+
+    /// Settings for expanding template strings
+    fn options(&self) -> ExpansionOptions {
+        ExpansionOptions::default()
+    }
+
+    /// Number of mapping applications currently being expanded
+    fn depth(&self) -> usize {
+        0
+    }
+
+    /// Name of the innermost mapping application being expanded, and the scope it was applied in
+    fn application(&self) -> Option<(&PathIdent, &dyn EvaluationContext<S>)> {
+        None
+    }
+
+    /// Names of the mapping applications currently being expanded, innermost first. Follows the
+    /// callers in a loop, the chain can be as long as `max_depth`.
+    fn call_chain(&self, chain: &mut Vec<String>) {
+        let mut application = self.application();
+        while let Some((name, caller)) = application {
+            chain.push(name.to_string());
+            application = caller.application();
+        }
+    }
+
+    /// Cache for memoized mapping applications
+    fn memo(&self) -> Option<&MemoCache> {
+        None
+    }
+}
+
+impl<S: SourceType> EvaluationContext<S> for &dyn EvaluationContext<S> {
+    fn lookup<'a>(&'a self, name: &PathIdent, args: &ArgValues<'a>) -> LookupResult<'a, S> {
+        (**self).lookup(name, args)
+    }
+
+    fn lookup_lazy(&self, name: &PathIdent) -> Option<(&Expr<S>, &dyn EvaluationContext<S>)> {
+        (**self).lookup_lazy(name)
+    }
+
+    fn options(&self) -> ExpansionOptions {
+        (**self).options()
+    }
+
+    fn depth(&self) -> usize {
+        (**self).depth()
+    }
+
+    fn application(&self) -> Option<(&PathIdent, &dyn EvaluationContext<S>)> {
+        (**self).application()
+    }
+
+    fn memo(&self) -> Option<&MemoCache> {
+        (**self).memo()
+    }
 }
 
 /// Local scope (~= stack frame)
+///
+/// Locals shadow the mappings of the same name in `parent`, but only for the applications they
+/// match. `[name]` refers to a parameter `name`, while `[name 1 2]` still resolves to a mapping
+/// `name` taking two arguments.
 pub struct ScopedContext<'parent, S: SourceType> {
-    /// Reference to the context below us (Global or another scope)
+    /// Reference to the enclosing scope, where names that aren't local are resolved (Global or
+    /// another scope)
     pub parent: &'parent dyn EvaluationContext<S>,
 
     /// Local variables added by this scope
-    pub locals: HashMap<String, Vec<Mapping<S>>>,
+    pub locals: HashMap<Symbol, Vec<Mapping<S>>>,
+
+    /// Unevaluated arguments of lazy parameters, together with the scope of the caller they are
+    /// expanded in
+    pub lazy_locals: HashMap<Symbol, (Expr<S>, &'parent dyn EvaluationContext<S>)>,
+}
+
+/// Maximum nesting of mapping applications, unless configured otherwise
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExpansionOptions {
+    /// Escaping applied to values interpolated into template strings
+    pub autoescape: Option<Escaping>,
+
+    /// Indent continuation lines of interpolated multi-line values to the column they are
+    /// interpolated at
+    pub reindent: bool,
+
+    /// Maximum nesting of mapping applications before expansion fails with
+    /// `ExpansionError::RecursionLimit`
+    pub max_depth: usize,
+}
+
+impl Default for ExpansionOptions {
+    fn default() -> Self {
+        Self {
+            autoescape: None,
+            reindent: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// Overrides the expansion options for everything expanded in it
+pub struct OptionsContext<'inner, S: SourceType> {
+    pub inner: &'inner dyn EvaluationContext<S>,
+    pub options: ExpansionOptions,
+}
+
+/// Provides the cache for memoized mapping applications to everything expanded in it
+pub struct MemoContext<'inner, S: SourceType> {
+    pub inner: &'inner dyn EvaluationContext<S>,
+    pub cache: &'inner MemoCache,
+}
+
+/// Entered for every application of a user defined mapping, tracks the call chain. Caches the
+/// options and the memo cache of the caller, so that they don't have to be looked up through the
+/// whole chain.
+pub struct CallContext<'inner, S: SourceType> {
+    /// Scope the mapping was defined in, where names in its translation are resolved
+    pub inner: &'inner dyn EvaluationContext<S>,
+    /// Scope the mapping was applied in
+    pub caller: &'inner dyn EvaluationContext<S>,
+    pub name: &'inner PathIdent,
+    pub depth: usize,
+    pub options: ExpansionOptions,
+    pub memo: Option<&'inner MemoCache>,
 }
 
 /// Merges another context into this one. Mutates `a` in place.
@@ -27,21 +162,13 @@ pub struct ScopedContext<'parent, S: SourceType> {
 /// If a key (variable/function name) exists in both, the mappings
 /// from `b` are appended to the list in `a`.
 pub fn merge_contexts<S: SourceType>(a: &mut ProgramContext<S>, b: ProgramContext<S>) {
-    for (key, mappings) in b {
-        a.entry(key).or_default().extend(mappings);
+    for (key, overloads) in b {
+        a.entry(key).or_default().append(overloads);
     }
 }
 
 pub fn get_owned_context(ctx: ProgramContext<Borrowed<'_>>) -> ProgramContext<Owned> {
     ctx.into_iter()
-        .map(|(key, mappings)| {
-            let owned_key = key.to_owned();
-            let owned_mappings = mappings
-                .into_iter()
-                .map(expandr_syntax::IntoOwned::into_owned)
-                .collect();
-
-            (owned_key, owned_mappings)
-        })
+        .map(|(key, overloads)| (key, overloads.into_owned()))
         .collect()
 }
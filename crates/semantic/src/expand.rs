@@ -1,23 +1,81 @@
-use expandr_syntax::source_type::Owned;
+use expandr_syntax::{
+    ast::Expr,
+    source_type::{Owned, SourceType},
+};
 
-use crate::{context::EvaluationContext, expansion_error::ExpansionResult};
+use std::io;
 
+use reblessive::{Stack, Stk};
+
+use crate::{
+    context::EvaluationContext,
+    expansion_error::{ExpansionError, ExpansionResult},
+};
+
+/// Expansion runs on a work stack on the heap instead of the native stack. Every nested expansion
+/// is a future that is pushed onto `stk` and driven by a single loop, so deeply recursive
+/// templates are bounded by memory and `max_depth` rather than the thread's stack.
+///
+/// The futures only ever run on the stack of their own expansion, they don't have to be `Send`.
+#[allow(async_fn_in_trait)]
 pub trait Expandable {
-    fn expand<Ctx: EvaluationContext<Owned>>(self, ctx: &Ctx) -> ExpansionResult;
+    async fn expand_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+    ) -> ExpansionResult;
+
+    /// Write the expansion to `out` while it is produced, for values that end up in the output
+    /// as they are. Returns the value instead if it can't be written as text directly, the
+    /// default for everything that has to be expanded as a whole.
+    async fn expand_into_on<Ctx: EvaluationContext<Owned>>(
+        &self,
+        stk: &mut Stk,
+        ctx: &Ctx,
+        _out: &mut dyn io::Write,
+    ) -> Result<Option<Expanded>, ExpansionError> {
+        self.expand_on(stk, ctx).await.map(Some)
+    }
+
+    /// Expand on a new work stack
+    fn expand<Ctx: EvaluationContext<Owned>>(&self, ctx: &Ctx) -> ExpansionResult {
+        Stack::new().enter(|stk| self.expand_on(stk, ctx)).finish()
+    }
+
+    /// Stream the expansion into `out` on a new work stack, see `expand_into_on`
+    fn expand_into<Ctx: EvaluationContext<Owned>>(
+        &self,
+        ctx: &Ctx,
+        out: &mut dyn io::Write,
+    ) -> Result<Option<Expanded>, ExpansionError> {
+        Stack::new()
+            .enter(|stk| self.expand_into_on(stk, ctx, out))
+            .finish()
+    }
+}
+
+/// Write text to the output of a streaming expansion
+pub(crate) fn write_text(out: &mut dyn io::Write, text: &str) -> Result<(), ExpansionError> {
+    out.write_all(text.as_bytes())
+        .map_err(|source| ExpansionError::Write { source })
 }
 
 #[derive(Clone, Debug)]
 pub enum Expanded {
     Str(String),
     Int(i64),
+    /// Text that is already escaped or trusted, e.g. the output of a template. Interpolating it is
+    /// never escaped again in autoescape mode.
+    Safe(String),
 }
 
 impl PartialEq for Expanded {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (Self::Str(a), Self::Str(b)) => a == b,
+            (Self::Str(a) | Self::Safe(a), Self::Str(b) | Self::Safe(b)) => a == b,
             (Self::Int(a), Self::Int(b)) => a == b,
-            (Self::Int(i), Self::Str(s)) | (Self::Str(s), Self::Int(i)) => {
+            (Self::Int(i), Self::Str(s) | Self::Safe(s))
+            | (Self::Str(s) | Self::Safe(s), Self::Int(i)) => {
                 if !s.is_empty() || *i < 0 || *i > (u32::MAX as i64) {
                     false
                 } else {
@@ -29,10 +87,77 @@ impl PartialEq for Expanded {
 }
 
 impl Expanded {
+    /// `0`, the empty string, `"0"` and `"false"` are false, everything else is true
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Self::Int(i) => *i != 0,
+            Self::Str(s) | Self::Safe(s) => !matches!(s.as_str(), "" | "0" | "false"),
+        }
+    }
+
     pub fn into_string(self) -> String {
         match self {
-            Self::Str(s) => s,
+            Self::Str(s) | Self::Safe(s) => s,
             Self::Int(i) => i.to_string(),
         }
     }
+
+    /// Expression to bind the value to a parameter, keeping safe text safe
+    pub fn into_expr<S: SourceType>(self) -> Expr<S> {
+        match self {
+            Self::Str(s) => Expr::String(s),
+            Self::Int(i) => Expr::Integer(i),
+            Self::Safe(s) => Expr::SafeString(s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expandable as _, Expanded};
+    use crate::{
+        context::{get_owned_context, ExpansionOptions, OptionsContext},
+        expansion_error::{ExpansionError, ExpansionResult},
+    };
+
+    /// Expand `src` with a maximum depth of `max_depth`
+    fn expand_with_depth(src: &str, max_depth: usize) -> ExpansionResult {
+        let ast = expandr_syntax::parse(src, None).unwrap();
+        let ctx = get_owned_context(ast.ctx.clone());
+        let ctx = OptionsContext {
+            inner: &ctx,
+            options: ExpansionOptions {
+                max_depth,
+                ..ExpansionOptions::default()
+            },
+        };
+        ast.expand(&ctx)
+    }
+
+    #[test]
+    fn deep_recursion() {
+        // Guard conditions are expanded on the work stack as well. Every level of this recursion
+        // happens in the guard of the level above, deeper than the native stack of a test thread
+        // allows in debug builds.
+        let src = "\
+map countdown 0 => 'liftoff'
+map countdown [n:int where [countdown [calc n - 1]]] => 'liftoff'
+[countdown 2000]
+";
+        let value = expand_with_depth(src, 3000).expect("Below the recursion limit");
+        assert_eq!(value, Expanded::Str("liftoff".to_string()));
+
+        // Typed parameters, `is` expressions and templates all nest on the work stack
+        let src = "\
+map ping [s:str] => [is s {
+    _ ? [ping [pong s]]
+}]
+map pong [s] => '''[s]'''
+[ping 'x']
+";
+        let Err(ExpansionError::RecursionLimit { chain, .. }) = expand_with_depth(src, 2000) else {
+            panic!("Expected the recursion limit to be hit");
+        };
+        assert_eq!(chain.len(), 2000);
+    }
 }
@@ -1,4 +1,4 @@
-use std::fmt;
+use std::{fmt, io};
 
 use expandr_syntax::{
     ast::{mapping::Args, PathIdent},
@@ -19,6 +19,54 @@ pub enum ExpansionError {
         file: &'static str,
         line: u32,
     },
+    BuiltinFailed {
+        builtin: String,
+        msg: String,
+        args: Args<Owned>,
+        file: &'static str,
+        line: u32,
+    },
+    /// Writing the output of a streaming expansion failed
+    Write { source: io::Error },
+    RecursionLimit {
+        limit: usize,
+        /// Names of the mapping applications being expanded, outermost first
+        chain: Vec<String>,
+        file: &'static str,
+        line: u32,
+    },
+    /// Overloads would match if it wasn't for the types of their parameters
+    TypeMismatch {
+        name: PathIdent,
+        args: Args<Owned>,
+        /// Position of the rejected argument, starting at 1
+        position: usize,
+        /// What the argument is, like "the integer 3"
+        found: Box<str>,
+        /// The parameters that reject it
+        expected: Vec<String>,
+        file: &'static str,
+        line: u32,
+    },
+    /// Several overloads match and none of them is more specific than the others
+    AmbiguousMapping {
+        name: PathIdent,
+        args: Args<Owned>,
+        /// Two of the matching definitions, with where they were defined
+        definitions: [String; 2],
+        file: &'static str,
+        line: u32,
+    },
+}
+
+impl ExpansionError {
+    /// The part of a call chain that keeps repeating at its end, the whole chain if there is none
+    fn repeating_part(chain: &[String]) -> &[String] {
+        let len = chain.len();
+        (1..=len / 2)
+            .find(|&period| chain[len - period..] == chain[len - 2 * period..len - period])
+            .map_or(chain, |period| &chain[len - period..])
+    }
 }
 
 impl fmt::Display for ExpansionError {
@@ -38,6 +86,84 @@ impl fmt::Display for ExpansionError {
                     "\
 | Mapping could not be resolved: <italic>{name:?} {args:#?}</>
 | <red>{msg}</>
+"
+                )
+            }
+            ExpansionError::BuiltinFailed {
+                builtin,
+                msg,
+                args,
+                file,
+                line,
+            } => {
+                print_raise_ctx(f, file, *line)?;
+
+                color_print::cwrite!(
+                    f,
+                    "\
+| Builtin failed: <italic>{builtin} {args:#?}</>
+| <red>{msg}</>
+"
+                )
+            }
+            ExpansionError::Write { source } => {
+                color_print::cwrite!(f, "| <red>Failed to write the output: {source}</>\n")
+            }
+            ExpansionError::RecursionLimit {
+                limit,
+                chain,
+                file,
+                line,
+            } => {
+                print_raise_ctx(f, file, *line)?;
+
+                let repeating = Self::repeating_part(chain);
+                let cycle = repeating.join(" -> ");
+                color_print::cwrite!(
+                    f,
+                    "\
+| Maximum expansion depth of {limit} exceeded, change it with `--max-depth`
+| <red>Repeating calls: <italic>{cycle} -> ...</></>
+"
+                )
+            }
+            ExpansionError::TypeMismatch {
+                name,
+                args,
+                position,
+                found,
+                expected,
+                file,
+                line,
+            } => {
+                print_raise_ctx(f, file, *line)?;
+
+                let expected = expected.join(" or ");
+                color_print::cwrite!(
+                    f,
+                    "\
+| Mapping could not be resolved: <italic>{name:?} {args:#?}</>
+| <red>No overload of `{name}` accepts {found} as argument {position}, expected {expected}</>
+"
+                )
+            }
+            ExpansionError::AmbiguousMapping {
+                name,
+                args,
+                definitions: [first, second],
+                file,
+                line,
+            } => {
+                print_raise_ctx(f, file, *line)?;
+
+                color_print::cwrite!(
+                    f,
+                    "\
+| Mapping application is ambiguous: <italic>{name:?} {args:#?}</>
+| <red>Matched equally well by</>
+|     <italic>{first}</>
+| <red>and</>
+|     <italic>{second}</>
 "
                 )
             }
@@ -61,3 +187,123 @@ macro_rules! undefined_mapping {
         )
     };
 }
+
+#[macro_export]
+macro_rules! builtin_failed {
+    (
+        $builtin:expr, $msg:expr, $args:expr
+    ) => {
+        Err($crate::expansion_error::ExpansionError::BuiltinFailed {
+            builtin: $builtin.to_string(),
+            msg: $msg.to_string(),
+            args: $args
+                .iter()
+                .cloned()
+                .map(expandr_syntax::IntoOwned::into_owned)
+                .collect(),
+            file: file!(),
+            line: line!(),
+        })
+    };
+}
+
+#[macro_export]
+macro_rules! recursion_limit {
+    (
+        $limit:expr, $ctx:expr
+    ) => {{
+        let mut chain = Vec::new();
+        $ctx.call_chain(&mut chain);
+        chain.reverse();
+        Err($crate::expansion_error::ExpansionError::RecursionLimit {
+            limit: $limit,
+            chain,
+            file: file!(),
+            line: line!(),
+        })
+    }};
+}
+
+#[macro_export]
+macro_rules! type_mismatch {
+    (
+        $name:expr, $args:expr, $position:expr, $found:expr, $expected:expr
+    ) => {
+        Err::<(), _>($crate::expansion_error::ExpansionError::TypeMismatch {
+            name: $name.clone(),
+            args: $args.to_vec(),
+            position: $position,
+            found: $found,
+            expected: $expected,
+            file: file!(),
+            line: line!(),
+        })
+    };
+}
+
+#[macro_export]
+macro_rules! ambiguous_mapping {
+    (
+        $name:expr, $args:expr, $first:expr, $second:expr
+    ) => {
+        Err($crate::expansion_error::ExpansionError::AmbiguousMapping {
+            name: $name.clone(),
+            args: $args.to_vec(),
+            definitions: [$first.to_string(), $second.to_string()],
+            file: file!(),
+            line: line!(),
+        })
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::{get_owned_context, ExpansionOptions, OptionsContext},
+        expand::Expandable as _,
+    };
+
+    #[test]
+    fn repeating_part() {
+        let chain = |names: &str| -> Vec<String> { names.split(' ').map(String::from).collect() };
+        assert_eq!(
+            ExpansionError::repeating_part(&chain("main a b c b c b c")),
+            chain("b c")
+        );
+        assert_eq!(ExpansionError::repeating_part(&chain("a a a")), chain("a"));
+        assert_eq!(
+            ExpansionError::repeating_part(&chain("a b c")),
+            chain("a b c")
+        );
+    }
+
+    #[test]
+    fn recursion_limit() {
+        // Counting down by two from an odd number never reaches the base case
+        let src = "\
+map ping [n] => [pong [calc n - 2]]
+map pong [n] => [is n {
+    .. 0 ? 'done'
+    .. _ ? [ping n]
+}]
+[ping 5]
+";
+        let ast = expandr_syntax::parse(src, None).unwrap();
+        let ctx = get_owned_context(ast.ctx.clone());
+        let ctx = OptionsContext {
+            inner: &ctx,
+            options: ExpansionOptions {
+                max_depth: 50,
+                ..ExpansionOptions::default()
+            },
+        };
+
+        let Err(ExpansionError::RecursionLimit { limit, chain, .. }) = ast.expand(&ctx) else {
+            panic!("Expected the recursion limit to be hit");
+        };
+        assert_eq!(limit, 50);
+        assert_eq!(chain.len(), 50);
+        assert_eq!(ExpansionError::repeating_part(&chain), ["ping", "pong"]);
+    }
+}
@@ -1,6 +1,8 @@
 pub mod ast_expansion;
 mod builtins;
+pub use builtins::{Escaping, ESCAPING_MODES};
 pub mod context;
 pub mod expand;
 pub mod expansion_error;
 pub mod mapping_lookup;
+pub mod memo;
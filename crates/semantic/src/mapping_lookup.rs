@@ -1,125 +1,1217 @@
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+};
+
 use expandr_syntax::{
     ast::{
-        mapping::{Mapping, MappingApplication, Param, ParamType, ParameterizedMapping, Params},
+        mapping::{
+            Guard, Mapping, MappingApplication, NamedArg, Param, ParamType, ParameterizedMapping,
+            Params,
+        },
         Expr, PathIdent,
     },
     log,
     source_type::{Owned, SourceType},
-    ProgramContext,
+    Origin, Overloads, ProgramContext,
 };
+use reblessive::Stk;
 
-use crate::context::{EvaluationContext, ScopedContext};
+use crate::{
+    ambiguous_mapping,
+    builtins::evaluate_math,
+    context::{
+        CallContext, EvaluationContext, ExpansionOptions, LookupResult, MemoContext,
+        OptionsContext, ScopedContext,
+    },
+    expand::{Expandable as _, Expanded},
+    expansion_error::{ExpansionError, ExpansionResult},
+    memo::MemoCache,
+    type_mismatch,
+};
 
-// ProgramContext: Global scope
-impl<S: SourceType> EvaluationContext<S> for ProgramContext<S>
-where
-    S::Str: std::borrow::Borrow<str>,
-{
-    // TODO: Return `MatchingNotFound` error on fail
-    fn lookup(&self, path_ident: &PathIdent, args: &[Expr<Owned>]) -> Option<&Mapping<S>> {
-        let formatted_mapping = format!("Name: {path_ident}\nArgs: {args:#?}");
+/// Arguments of a mapping application
+///
+/// Overloads with `int` or `str` parameters have to know the values of arguments to match them.
+/// These are expanded in the scope of the application, and kept for binding the parameters of the
+/// overload that is applied.
+///
+/// Lookup itself doesn't expand anything, so that it can't recurse into other expansions. It
+/// requests the first argument or `where` guard it is missing the value of, and doesn't match
+/// overloads that depend on it. `resolve` expands what is requested on the work stack and looks
+/// up again, until lookup gets by with the values it has.
+pub struct ArgValues<'a> {
+    pub exprs: &'a [Expr<Owned>],
+    /// Arguments passed by name, bound before the positional ones
+    pub named: &'a [NamedArg<Owned>],
+    /// Scope of the application
+    pub ctx: &'a dyn EvaluationContext<Owned>,
+    /// Expanded arguments by the address of their expression, arguments nested in structural
+    /// patterns included
+    values: RefCell<HashMap<usize, Expanded>>,
+    /// Arguments that failed to expand, by the address of their expression
+    failed: RefCell<HashSet<usize>>,
+    /// Whether arguments pass `where` guards, by the addresses of the parameter and the argument
+    guards: RefCell<HashMap<(usize, usize), bool>>,
+    /// Value lookup needs
+    request: Cell<Option<Request<'a>>>,
+    /// First error of expanding an argument for matching. It is only reported if no overload
+    /// matches.
+    error: RefCell<Option<ExpansionError>>,
+}
 
-        // At least an entry for this name?
-        let Some(name_matches) = &self.get(path_ident.name()) else {
-            log!("No name matching found for {path_ident} in ProgramContext, there might have been matchings in subscopes, but their arguments didn't match.\n\n{formatted_mapping}");
-            return None;
+impl<'a> ArgValues<'a> {
+    pub fn new(exprs: &'a [Expr<Owned>], ctx: &'a dyn EvaluationContext<Owned>) -> Self {
+        Self {
+            exprs,
+            named: &[],
+            ctx,
+            values: RefCell::default(),
+            failed: RefCell::default(),
+            guards: RefCell::default(),
+            request: Cell::default(),
+            error: RefCell::default(),
+        }
+    }
+
+    pub fn with_named(self, named: &'a [NamedArg<Owned>]) -> Self {
+        Self { named, ..self }
+    }
+
+    /// Look up the overload of `name` matching the arguments in the scope of the application,
+    /// expanding the arguments lookup asks for on `stk`
+    pub async fn resolve(&self, stk: &mut Stk, name: &PathIdent) -> LookupResult<'a, Owned> {
+        loop {
+            let found = self.ctx.lookup(name, self);
+            let Some(request) = self.request.take() else {
+                return found;
+            };
+            match request {
+                Request::Arg(expr) => {
+                    let key = expr as *const Expr<Owned> as usize;
+                    match stk.run(|stk| expr.expand_on(stk, &self.ctx)).await {
+                        Ok(value) => {
+                            self.values.borrow_mut().insert(key, value);
+                        }
+                        Err(error) => {
+                            self.record(error);
+                            self.failed.borrow_mut().insert(key);
+                        }
+                    }
+                }
+                Request::Guard { scope, param, arg } => {
+                    let passes = self.expand_guard(stk, scope, name, param, arg).await;
+                    self.guards
+                        .borrow_mut()
+                        .insert(guard_key(param, arg), passes);
+                }
+            }
+        }
+    }
+
+    /// Whether `arg` passes the `where` guard of `param`. The condition is expanded on `stk` in
+    /// `scope`, where the mapping is defined, with only the parameter bound.
+    async fn expand_guard(
+        &self,
+        stk: &mut Stk,
+        scope: &dyn EvaluationContext<Owned>,
+        mapping: &PathIdent,
+        param: &Param,
+        arg: &'a Expr<Owned>,
+    ) -> bool {
+        let Param::ParamExpr {
+            name,
+            typ,
+            guard: Some(Guard::Where { condition, .. }),
+            ..
+        } = param
+        else {
+            unreachable!("Only `where` guards are requested");
         };
+        let value = (self.bound_value_now(arg, *typ))
+            .expect("Guards are requested once the argument is expanded");
+        let call_ctx = CallContext {
+            inner: scope,
+            caller: self.ctx,
+            name: mapping,
+            depth: self.ctx.depth() + 1,
+            options: self.ctx.options(),
+            memo: self.ctx.memo(),
+        };
+        let guard_ctx = ScopedContext {
+            parent: &call_ctx,
+            locals: HashMap::from([(name.name(), vec![Mapping::SimpleMapping(value.into_expr())])]),
+            lazy_locals: HashMap::new(),
+        };
+        let passes = match &condition[..] {
+            [condition] => stk.run(|stk| condition.expand_on(stk, &guard_ctx)).await,
+            // Calculated by the builtin, whatever `calc` refers to where the mapping is defined
+            _ => {
+                stk.run(|stk| evaluate_math(stk, &guard_ctx, condition))
+                    .await
+            }
+        };
+        match passes {
+            Ok(value) => value.is_truthy(),
+            Err(error) => {
+                self.record(error);
+                false
+            }
+        }
+    }
+
+    /// Argument `i`, counting the named arguments after the positional ones
+    pub fn arg(&self, i: usize) -> &'a Expr<Owned> {
+        match self.exprs.get(i) {
+            Some(arg) => arg,
+            None => &self.named[i - self.exprs.len()].value,
+        }
+    }
 
-        // Filter the name matches. Only retain the ones where the arguments match the expected
-        // parameters
-        let mut arg_matches = name_matches
+    /// The argument each of `params` is bound to, as for `arg`, `None` for the ones that take
+    /// their default value. Named arguments are bound first. The positional ones go to the
+    /// remaining required parameters, and to optional ones from the left as long as there are
+    /// more arguments than required parameters. `None` if the arguments don't fit.
+    pub fn arrange(&self, params: &Params) -> Option<Vec<Option<usize>>> {
+        let mut slots = vec![None; params.entries.len()];
+        for (i, named) in self.named.iter().enumerate() {
+            let position = params.entries.iter().position(
+                |param| matches!(param, Param::ParamExpr { name, .. } if name.name() == named.name),
+            )?;
+            if slots[position].replace(self.exprs.len() + i).is_some() {
+                return None;
+            }
+        }
+
+        let (mut required, mut optional) = (0, 0);
+        for (param, _) in params
+            .entries
             .iter()
-            .filter(|m| mapping_matches_args(m, args));
-        // Clone the iterator for printing later
-        let arg_matches_copy = arg_matches.clone();
+            .zip(&slots)
+            .filter(|(_, s)| s.is_none())
+        {
+            if param.is_optional() {
+                optional += 1;
+            } else {
+                required += 1;
+            }
+        }
+        let mut extra = self.exprs.len().checked_sub(required)?;
+        if extra > optional {
+            return None;
+        }
+
+        let mut positional = 0..self.exprs.len();
+        for (param, slot) in params.entries.iter().zip(&mut slots) {
+            if slot.is_some() {
+                continue;
+            }
+            if param.is_optional() {
+                if extra == 0 {
+                    continue;
+                }
+                extra -= 1;
+            }
+            *slot = positional.next();
+        }
+        Some(slots)
+    }
+
+    /// The parameter each argument is bound to, and how many parameters take their default
+    fn bound_params<'p>(&self, params: &'p Params) -> (Vec<&'p Param>, usize) {
+        let slots = self
+            .arrange(params)
+            .expect("Only called for matching overloads");
+        let mut bound = vec![None; self.exprs.len() + self.named.len()];
+        let mut defaults = 0;
+        for (param, slot) in params.entries.iter().zip(slots) {
+            match slot {
+                Some(i) => bound[i] = Some(param),
+                None => defaults += 1,
+            }
+        }
+        let bound = bound
+            .into_iter()
+            .map(|param| param.expect("All arguments are bound"))
+            .collect();
+        (bound, defaults)
+    }
+
+    /// Value of `expr`, which has to be one of the arguments or nested in one
+    pub async fn expand(&self, stk: &mut Stk, expr: &Expr<Owned>) -> ExpansionResult {
+        let key = expr as *const Expr<Owned> as usize;
+        if let Some(value) = self.values.borrow().get(&key) {
+            return Ok(value.clone());
+        }
+        let value = stk.run(|stk| expr.expand_on(stk, &self.ctx)).await?;
+        self.values.borrow_mut().insert(key, value.clone());
+        Ok(value)
+    }
+
+    /// Value of `expr` if it is expanded already. Requests it otherwise, unless it failed to
+    /// expand.
+    fn cached(&self, expr: &'a Expr<Owned>) -> Option<Expanded> {
+        let key = expr as *const Expr<Owned> as usize;
+        if let Some(value) = self.values.borrow().get(&key) {
+            return Some(value.clone());
+        }
+        if !self.failed.borrow().contains(&key) && self.request.get().is_none() {
+            self.request.set(Some(Request::Arg(expr)));
+        }
+        None
+    }
+
+    /// Value of `expr` for matching, `None` if it can't be expanded or isn't yet
+    fn value(&self, expr: &'a Expr<Owned>) -> Option<Expanded> {
+        // Undefined idents are names, like `ax`, and not an error
+        if let Expr::PathIdent(ident) = expr {
+            let key = expr as *const Expr<Owned> as usize;
+            if !self.values.borrow().contains_key(&key) && !self.is_defined(ident) {
+                return None;
+            }
+        }
+        self.cached(expr)
+    }
+
+    fn record(&self, error: ExpansionError) {
+        self.error.borrow_mut().get_or_insert(error);
+    }
+
+    /// Value bound to a parameter of type `typ` for `arg`
+    pub async fn bound_value(
+        &self,
+        stk: &mut Stk,
+        arg: &Expr<Owned>,
+        typ: ParamType,
+    ) -> ExpansionResult {
+        match literal_value(arg, typ) {
+            Some(value) => Ok(value),
+            None => Ok(as_type(self.expand(stk, arg).await?, typ)),
+        }
+    }
+
+    /// `bound_value` for matching, `None` if the argument isn't expanded yet
+    fn bound_value_now(&self, arg: &'a Expr<Owned>, typ: ParamType) -> Option<Expanded> {
+        literal_value(arg, typ).or_else(|| Some(as_type(self.cached(arg)?, typ)))
+    }
+
+    /// Text of `arg` for regex guards: Symbols and undefined idents (`ax`) as written, other
+    /// arguments, like parameters passed on, as what they expand to
+    pub async fn text(&self, stk: &mut Stk, arg: &Expr<Owned>) -> Result<String, ExpansionError> {
+        match self.literal_text(arg) {
+            Some(text) => Ok(text),
+            None => Ok(self.expand(stk, arg).await?.into_string()),
+        }
+    }
+
+    /// Text of `arg` if it is taken as written
+    fn literal_text(&self, arg: &Expr<Owned>) -> Option<String> {
+        match arg {
+            Expr::PathIdent(id) if !self.is_defined(id) => Some(id.original_src.to_string()),
+            Expr::LiteralSymbol(c) => Some(c.to_string()),
+            Expr::LiteralOperator(op) => Some(op.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Whether `ident` can be expanded in the scope of the application
+    fn is_defined(&self, ident: &PathIdent) -> bool {
+        self.ctx.lookup_lazy(ident).is_some()
+            || matches!(
+                self.ctx.lookup(ident, &ArgValues::new(&[], self.ctx)),
+                Ok(Some(_))
+            )
+    }
+
+    /// Whether `arg` passes the guard of `param`, which belongs to an overload defined in
+    /// `scope`. Requests the result of `where` guards that aren't expanded yet.
+    fn passes_guard(
+        &self,
+        scope: &'a dyn EvaluationContext<Owned>,
+        param: &'a Param,
+        arg: &'a Expr<Owned>,
+    ) -> bool {
+        let Param::ParamExpr {
+            typ,
+            guard: Some(guard),
+            ..
+        } = param
+        else {
+            return true;
+        };
+        match guard {
+            Guard::Regex { regex, .. } => {
+                let text = (self.literal_text(arg))
+                    .or_else(|| self.cached(arg).map(Expanded::into_string));
+                text.is_some_and(|text| regex.is_match(&text))
+            }
+            Guard::Where { .. } => {
+                if self.bound_value_now(arg, *typ).is_none() {
+                    return false;
+                }
+                if let Some(passes) = self.guards.borrow().get(&guard_key(param, arg)) {
+                    return *passes;
+                }
+                if self.request.get().is_none() {
+                    self.request.set(Some(Request::Guard { scope, param, arg }));
+                }
+                false
+            }
+        }
+    }
+
+    /// Whether lookup is waiting for the value of an argument, its result doesn't count then
+    fn is_waiting(&self) -> bool {
+        self.request.get().is_some()
+    }
 
-        // At least one full match?
-        let Some(first_arg_match) = arg_matches.next() else {
-            log!("Found some name matchings for {path_ident}, but arguments didn't match.\n\n{formatted_mapping}\n\nCandidate(s): {name_matches:#?}");
+    /// The error of expanding an argument, unless lookup is waiting for another one
+    fn take_error(&self) -> Option<ExpansionError> {
+        if self.is_waiting() {
             return None;
+        }
+        self.error.borrow_mut().take()
+    }
+
+    /// Whether `arg` has the type of a typed parameter
+    fn has_type(&self, arg: &'a Expr<Owned>, typ: ParamType) -> bool {
+        match (typ, arg) {
+            (ParamType::Block, Expr::Block(_))
+            | (ParamType::Sym, Expr::LiteralSymbol(_) | Expr::LiteralOperator(_)) => true,
+            (ParamType::Block | ParamType::Sym, _)
+            | (_, Expr::LiteralSymbol(_) | Expr::LiteralOperator(_)) => false,
+
+            (ParamType::Int, Expr::Integer(_))
+            | (ParamType::Str, Expr::String(_) | Expr::StrRef(_) | Expr::SafeString(_)) => true,
+            (ParamType::Int, Expr::String(_) | Expr::StrRef(_) | Expr::SafeString(_))
+            | (ParamType::Str, Expr::Integer(_)) => false,
+            (ParamType::Int, _) => matches!(self.value(arg), Some(Expanded::Int(_))),
+            (ParamType::Str, _) => {
+                matches!(self.value(arg), Some(Expanded::Str(_) | Expanded::Safe(_)))
+            }
+
+            (ParamType::Expr | ParamType::Ident | ParamType::Lazy, _) => {
+                unreachable!("Not a value type: {typ:?}")
+            }
+        }
+    }
+
+    /// What `arg` is, for error messages
+    fn describe(&self, arg: &'a Expr<Owned>) -> String {
+        match arg {
+            Expr::LiteralSymbol(c) => format!("the symbol `{c}`"),
+            Expr::LiteralOperator(op) => format!("the symbol `{op}`"),
+            Expr::PathIdent(id) => format!("the ident `{id}`"),
+            Expr::Block(_) => "a block".to_string(),
+            _ => match self.value(arg) {
+                Some(Expanded::Int(n)) => format!("the integer {n}"),
+                Some(Expanded::Str(s) | Expanded::Safe(s)) => format!("the string {s:?}"),
+                None => "an argument".to_string(),
+            },
+        }
+    }
+}
+
+/// What lookup needs to be expanded before it can go on
+#[derive(Clone, Copy)]
+enum Request<'a> {
+    /// Value of an argument
+    Arg(&'a Expr<Owned>),
+    /// Whether an argument passes the `where` guard of a parameter, defined in `scope`
+    Guard {
+        scope: &'a dyn EvaluationContext<Owned>,
+        param: &'a Param,
+        arg: &'a Expr<Owned>,
+    },
+}
+
+fn guard_key(param: &Param, arg: &Expr<Owned>) -> (usize, usize) {
+    (
+        param as *const Param as usize,
+        arg as *const Expr<Owned> as usize,
+    )
+}
+
+/// Value bound to a parameter of type `typ` for `arg` if the argument isn't expanded for it
+fn literal_value(arg: &Expr<Owned>, typ: ParamType) -> Option<Expanded> {
+    match (typ, arg) {
+        (ParamType::Ident, Expr::PathIdent(id)) => Some(Expanded::Str(id.original_src.to_string())),
+        (ParamType::Sym, Expr::LiteralSymbol(c)) => Some(Expanded::Str(c.to_string())),
+        (ParamType::Sym, Expr::LiteralOperator(op)) => Some(Expanded::Str(op.to_string())),
+        _ => None,
+    }
+}
+
+/// In Expr::parse, idents are also parsed as mapping applications. Ident parameters bind what they
+/// expand to as text.
+fn as_type(value: Expanded, typ: ParamType) -> Expanded {
+    match typ {
+        ParamType::Ident => Expanded::Str(value.into_string()),
+        _ => value,
+    }
+}
+
+// ProgramContext: Global scope
+impl EvaluationContext<Owned> for ProgramContext<Owned> {
+    // TODO: Return `MatchingNotFound` error on fail
+    fn lookup<'a>(
+        &'a self,
+        path_ident: &PathIdent,
+        args: &ArgValues<'a>,
+    ) -> LookupResult<'a, Owned> {
+        // At least an entry for this name? Definitions are keyed by their full path, so `a/b`
+        // doesn't resolve to a mapping `b`
+        let Some(overloads) = self.get(&path_ident.canonical()) else {
+            log!("No name matching found for {path_ident} in ProgramContext, there might have been matchings in subscopes, but their arguments didn't match.\n\nName: {path_ident}\nArgs: {:#?}", args.exprs);
+            return Ok(None);
         };
 
-        // TODO: Precedence for raw idents vs exprs
-        Some(first_arg_match)
+        // Only the overloads the dispatch index leads to can match. Retain the ones where the
+        // arguments match the expected parameters
+        let arg_matches: Vec<_> = overloads
+            .candidates(args.exprs, !args.named.is_empty())
+            .filter(|(m, _)| {
+                Matcher {
+                    args,
+                    scope: self,
+                    typed: true,
+                }
+                .matches(m)
+            })
+            .collect();
+        if args.is_waiting() {
+            return Ok(None);
+        }
+
+        if arg_matches.is_empty() {
+            log!("Found some name matchings for {path_ident}, but arguments didn't match.\n\nName: {path_ident}\nArgs: {:#?}\n\nCandidate(s): {overloads:#?}", args.exprs);
+            let mismatch = find_type_mismatch(path_ident, overloads, args, self);
+            // Arguments that can't be expanded are an error anyways
+            if let Some(error) = args.take_error() {
+                return Err(error);
+            }
+            return mismatch.map_or(Ok(None), Err);
+        }
+
+        // The most specific match wins, it has to be more specific than all others
+        let is_dominated = |(m, _): &(&Mapping<Owned>, Origin)| {
+            arg_matches.iter().any(|(other, _)| {
+                compare_overloads(args, params_of(other), params_of(m)) == Some(Ordering::Greater)
+            })
+        };
+        let mut best = arg_matches.iter().filter(|m| !is_dominated(m));
+        match (best.next(), best.next()) {
+            (Some(&(m, _)), None) => Ok(Some((m, self))),
+            (Some(&(first, first_origin)), Some(&(second, second_origin))) => ambiguous_mapping!(
+                path_ident,
+                args.exprs,
+                format!("map {path_ident} {} ({first_origin})", params_of(first)),
+                format!("map {path_ident} {} ({second_origin})", params_of(second))
+            ),
+            (None, _) => unreachable!("Specificity is a partial order, a maximum exists"),
+        }
     }
 }
 
 // ScopedContext: Local scopes
 impl<'parent, S: SourceType> EvaluationContext<S> for ScopedContext<'parent, S> {
-    fn lookup(&self, path_ident: &PathIdent, args: &[Expr<Owned>]) -> Option<&Mapping<S>> {
-        // Try lookup locally first
-        if let Some(name_matches) = self.locals.get(path_ident.name()) {
-            let mut arg_matches = name_matches
-                .iter()
-                .filter(|&m| mapping_matches_args(m, args));
-            return arg_matches.next();
+    fn lookup<'a>(&'a self, path_ident: &PathIdent, args: &ArgValues<'a>) -> LookupResult<'a, S> {
+        // Try lookup locally first. Locals are bound values, they don't take arguments, named
+        // ones neither.
+        if let Some(local) = self
+            .locals
+            .get(&path_ident.canonical())
+            .filter(|_| args.exprs.is_empty() && args.named.is_empty())
+            .and_then(|name_matches| name_matches.first())
+        {
+            return Ok(Some((local, self)));
         }
-        // Delegate lookup to parent
+        // Delegate lookup to parent, also if the locals of that name don't take these arguments
         self.parent.lookup(path_ident, args)
     }
+
+    fn lookup_lazy(&self, path_ident: &PathIdent) -> Option<(&Expr<S>, &dyn EvaluationContext<S>)> {
+        if let Some((expr, caller)) = self.lazy_locals.get(&path_ident.canonical()) {
+            return Some((expr, *caller));
+        }
+        // Shadowed by a regular local
+        if self.locals.contains_key(&path_ident.canonical()) {
+            return None;
+        }
+        self.parent.lookup_lazy(path_ident)
+    }
+
+    fn options(&self) -> ExpansionOptions {
+        self.parent.options()
+    }
+
+    fn depth(&self) -> usize {
+        self.parent.depth()
+    }
+
+    fn application(&self) -> Option<(&PathIdent, &dyn EvaluationContext<S>)> {
+        self.parent.application()
+    }
+
+    fn memo(&self) -> Option<&MemoCache> {
+        self.parent.memo()
+    }
 }
 
-fn mapping_matches_args<S: SourceType>(mapping: &Mapping<S>, args: &[Expr<Owned>]) -> bool {
+// OptionsContext: Wraps another context to change the expansion options
+impl<'inner, S: SourceType> EvaluationContext<S> for OptionsContext<'inner, S> {
+    fn lookup<'a>(&'a self, path_ident: &PathIdent, args: &ArgValues<'a>) -> LookupResult<'a, S> {
+        self.inner.lookup(path_ident, args)
+    }
+
+    fn lookup_lazy(&self, path_ident: &PathIdent) -> Option<(&Expr<S>, &dyn EvaluationContext<S>)> {
+        self.inner.lookup_lazy(path_ident)
+    }
+
+    fn options(&self) -> ExpansionOptions {
+        self.options
+    }
+
+    fn depth(&self) -> usize {
+        self.inner.depth()
+    }
+
+    fn application(&self) -> Option<(&PathIdent, &dyn EvaluationContext<S>)> {
+        self.inner.application()
+    }
+
+    fn memo(&self) -> Option<&MemoCache> {
+        self.inner.memo()
+    }
+}
+
+// MemoContext: Wraps another context to provide the memo cache
+impl<'inner, S: SourceType> EvaluationContext<S> for MemoContext<'inner, S> {
+    fn lookup<'a>(&'a self, path_ident: &PathIdent, args: &ArgValues<'a>) -> LookupResult<'a, S> {
+        self.inner.lookup(path_ident, args)
+    }
+
+    fn lookup_lazy(&self, path_ident: &PathIdent) -> Option<(&Expr<S>, &dyn EvaluationContext<S>)> {
+        self.inner.lookup_lazy(path_ident)
+    }
+
+    fn options(&self) -> ExpansionOptions {
+        self.inner.options()
+    }
+
+    fn depth(&self) -> usize {
+        self.inner.depth()
+    }
+
+    fn application(&self) -> Option<(&PathIdent, &dyn EvaluationContext<S>)> {
+        self.inner.application()
+    }
+
+    fn memo(&self) -> Option<&MemoCache> {
+        Some(self.cache)
+    }
+}
+
+// CallContext: Wraps another context to record a mapping application
+impl<'inner, S: SourceType> EvaluationContext<S> for CallContext<'inner, S> {
+    fn lookup<'a>(&'a self, path_ident: &PathIdent, args: &ArgValues<'a>) -> LookupResult<'a, S> {
+        self.inner.lookup(path_ident, args)
+    }
+
+    fn lookup_lazy(&self, path_ident: &PathIdent) -> Option<(&Expr<S>, &dyn EvaluationContext<S>)> {
+        self.inner.lookup_lazy(path_ident)
+    }
+
+    fn options(&self) -> ExpansionOptions {
+        self.options
+    }
+
+    fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn application(&self) -> Option<(&PathIdent, &dyn EvaluationContext<S>)> {
+        Some((self.name, self.caller))
+    }
+
+    fn memo(&self) -> Option<&MemoCache> {
+        self.memo
+    }
+}
+
+fn params_of<S: SourceType>(mapping: &Mapping<S>) -> &Params {
+    static NO_PARAMS: Params = Params {
+        entries: Vec::new(),
+    };
     match mapping {
-        Mapping::SimpleMapping(_) => args.is_empty(),
-        Mapping::ParameterizedMapping(ParameterizedMapping { params, .. }) => {
-            matches_args(params, args)
+        Mapping::SimpleMapping(_) => &NO_PARAMS,
+        Mapping::ParameterizedMapping(ParameterizedMapping { params, .. }) => params,
+    }
+}
+
+/// Compares two overloads matching `args` by the parameters each argument is bound to, as
+/// `compare_specificity` does. Overloads that leave fewer parameters to their default values are
+/// more specific in addition.
+fn compare_overloads(args: &ArgValues, a: &Params, b: &Params) -> Option<Ordering> {
+    let (a_params, a_defaults) = args.bound_params(a);
+    let (b_params, b_defaults) = args.bound_params(b);
+    let ordering = compare_each(a_params.into_iter().zip(b_params))?;
+    combine(ordering, b_defaults.cmp(&a_defaults))
+}
+
+/// Compares how specific the parameters of two overloads matching the same arguments are. One is
+/// more specific than the other if none of its parameters is less specific and at least one is
+/// more specific. `None` if neither is.
+fn compare_specificity(a: &Params, b: &Params) -> Option<Ordering> {
+    compare_each(a.entries.iter().zip(&b.entries))
+}
+
+fn compare_each<'p>(pairs: impl Iterator<Item = (&'p Param, &'p Param)>) -> Option<Ordering> {
+    let mut ordering = Ordering::Equal;
+    for (a, b) in pairs {
+        let param_ordering = match (a, b) {
+            (Param::Application { params: a, .. }, Param::Application { params: b, .. })
+            | (Param::Block(a), Param::Block(b)) => compare_specificity(a, b)?,
+            _ => specificity(a).cmp(&specificity(b)),
+        };
+        ordering = combine(ordering, param_ordering)?;
+    }
+    Some(ordering)
+}
+
+/// Ordering of two orderings in different respects, `None` if they contradict each other
+fn combine(ordering: Ordering, other: Ordering) -> Option<Ordering> {
+    match (ordering, other) {
+        (_, Ordering::Equal) => Some(ordering),
+        (Ordering::Equal, _) => Some(other),
+        _ if ordering != other => None,
+        _ => Some(ordering),
+    }
+}
+
+/// Literals over guarded captures over typed captures over untyped captures
+fn specificity(param: &Param) -> u8 {
+    match param {
+        Param::Ident(_)
+        | Param::Symbol(_)
+        | Param::Operator(_)
+        | Param::Integer(_)
+        | Param::String(_)
+        | Param::Application { .. }
+        | Param::Block(_) => 4,
+        Param::ParamExpr { guard: Some(_), .. } => 3,
+        Param::ParamExpr {
+            typ: ParamType::Expr | ParamType::Lazy,
+            ..
+        } => 1,
+        Param::ParamExpr { .. } => 2,
+    }
+}
+
+/// Error for arguments that only fail to match because of the types or guards of parameters.
+/// Reports the first positional argument a typed parameter rejects.
+fn find_type_mismatch<'a>(
+    path_ident: &PathIdent,
+    overloads: &'a Overloads<Owned>,
+    args: &ArgValues<'a>,
+    scope: &'a dyn EvaluationContext<Owned>,
+) -> Option<ExpansionError> {
+    let matcher = Matcher {
+        args,
+        scope,
+        typed: false,
+    };
+    let typed = Matcher {
+        typed: true,
+        ..matcher
+    };
+    // Position of the first rejected argument of each overload that matches without types
+    let rejections: Vec<_> = overloads
+        .candidates(args.exprs, !args.named.is_empty())
+        .filter_map(|(m, _)| {
+            let params = params_of(m);
+            let bound: Vec<_> = params
+                .entries
+                .iter()
+                .zip(args.arrange(params)?)
+                .filter_map(|(param, slot)| Some((slot?, param)))
+                .collect();
+            if !bound
+                .iter()
+                .all(|(i, param)| matcher.matches_arg(param, args.arg(*i)))
+            {
+                return None;
+            }
+            bound
+                .into_iter()
+                .filter(|(i, param)| {
+                    *i < args.exprs.len() && !typed.matches_arg(param, args.arg(*i))
+                })
+                .min_by_key(|(i, _)| *i)
+        })
+        .collect();
+
+    let position = rejections.iter().map(|(position, _)| *position).min()?;
+    let mut expected = Vec::new();
+    for (_, param) in rejections.iter().filter(|(p, _)| *p == position) {
+        let param = format!("`{param}`");
+        if !expected.contains(&param) {
+            expected.push(param);
         }
     }
+    type_mismatch!(
+        path_ident,
+        args.exprs,
+        position + 1,
+        args.describe(&args.exprs[position]).into(),
+        expected
+    )
+    .err()
 }
 
-fn matches_args<S: SourceType>(params: &Params, args: &[Expr<S>]) -> bool {
-    params.entries.len() == args.len()
-        && params
-            .entries
-            .iter()
-            .zip(args.iter())
-            .all(|(param, arg)| matches_arg(param, arg))
+/// Matches arguments against the parameters of the overloads of a mapping
+#[derive(Clone, Copy)]
+struct Matcher<'m, 'a> {
+    args: &'m ArgValues<'a>,
+    /// Where the overloads are defined, for the conditions of guards
+    scope: &'a dyn EvaluationContext<Owned>,
+    /// Typed and guarded parameters match any argument unless this is set
+    typed: bool,
 }
 
-fn matches_arg<S: SourceType>(param: &Param, arg: &Expr<S>) -> bool {
-    match (param, arg) {
-        // Evaluated expressions
-        (
-            Param::ParamExpr {
-                typ: ParamType::Expr,
-                ..
-            },
-            Expr::Integer(_)
-            | Expr::String(_)
-            | Expr::StrRef(_)
-            | Expr::TemplateString(_)
-            | Expr::MappingApplication(_)
-            | Expr::PathIdent(_)
-            | Expr::Block(_),
-        ) => true,
-
-        // Idents
-        (
+impl<'a> Matcher<'_, 'a> {
+    fn matches(&self, mapping: &'a Mapping<Owned>) -> bool {
+        match mapping {
+            Mapping::SimpleMapping(_) => self.args.exprs.is_empty() && self.args.named.is_empty(),
+            Mapping::ParameterizedMapping(ParameterizedMapping { params, .. })
+                if self.args.named.is_empty() && !params.entries.iter().any(Param::is_optional) =>
+            {
+                self.matches_args(params, self.args.exprs)
+            }
+            Mapping::ParameterizedMapping(ParameterizedMapping { params, .. }) => {
+                let Some(slots) = self.args.arrange(params) else {
+                    return false;
+                };
+                params.entries.iter().zip(slots).all(|(param, slot)| {
+                    slot.is_none_or(|i| self.matches_arg(param, self.args.arg(i)))
+                })
+            }
+        }
+    }
+
+    fn matches_args(&self, params: &'a Params, exprs: &'a [Expr<Owned>]) -> bool {
+        params.entries.len() == exprs.len()
+            && params
+                .entries
+                .iter()
+                .zip(exprs.iter())
+                .all(|(param, arg)| self.matches_arg(param, arg))
+    }
+
+    fn matches_arg(&self, param: &'a Param, arg: &'a Expr<Owned>) -> bool {
+        match param {
+            // Repetition isn't implemented yet
+            Param::ParamExpr { rep: Some(_), .. } => false,
+            // Regex guards take the place of the type
             Param::ParamExpr {
-                typ: ParamType::Ident,
+                guard: Some(Guard::Regex { .. }),
                 ..
-            },
-            Expr::PathIdent(_),
-        ) => true,
-        (
+            } => !self.typed || self.args.passes_guard(self.scope, param, arg),
             Param::ParamExpr {
-                typ: ParamType::Ident,
+                guard: Some(Guard::Where { .. }),
                 ..
+            } => {
+                self.matches_unguarded(param, arg)
+                    && (!self.typed || self.args.passes_guard(self.scope, param, arg))
+            }
+            _ => self.matches_unguarded(param, arg),
+        }
+    }
+
+    fn matches_unguarded(&self, param: &'a Param, arg: &'a Expr<Owned>) -> bool {
+        match (param, arg) {
+            // Typed values
+            (
+                Param::ParamExpr {
+                    typ: typ @ (ParamType::Int | ParamType::Str | ParamType::Block | ParamType::Sym),
+                    ..
+                },
+                _,
+            ) => !self.typed || self.args.has_type(arg, *typ),
+            (
+                Param::ParamExpr {
+                    typ: ParamType::Ident,
+                    ..
+                },
+                _,
+            ) if !self.typed => true,
+
+            // Evaluated expressions
+            (
+                Param::ParamExpr {
+                    typ: ParamType::Expr | ParamType::Lazy,
+                    ..
+                },
+                Expr::Integer(_)
+                | Expr::String(_)
+                | Expr::StrRef(_)
+                | Expr::SafeString(_)
+                | Expr::TemplateString(_)
+                | Expr::MappingApplication(_)
+                | Expr::PathIdent(_)
+                | Expr::Block(_)
+                | Expr::Is(_),
+            ) => true,
+
+            // Idents
+            (
+                Param::ParamExpr {
+                    typ: ParamType::Ident,
+                    ..
+                },
+                Expr::PathIdent(_),
+            ) => true,
+            (
+                Param::ParamExpr {
+                    typ: ParamType::Ident,
+                    ..
+                },
+                Expr::MappingApplication(appl),
+            ) if appl.args.is_empty() && appl.named.is_empty() => true, // In Expr::parse, idents are also parsed as mapping
+            // applications
+
+            // Raw literal matches
+            (Param::Ident(self_value), Expr::PathIdent(other_value)) => self_value == other_value,
+            (
+                Param::Ident(self_value),
+                Expr::MappingApplication(MappingApplication { name, args, named }),
+            ) if args.is_empty() && named.is_empty() => self_value == name,
+            (Param::Symbol(self_value), Expr::LiteralSymbol(other_value)) => {
+                self_value == other_value
+            }
+            (Param::Operator(self_value), Expr::LiteralOperator(other_value)) => {
+                self_value == other_value
+            }
+            (Param::Integer(self_value), Expr::Integer(other_value)) => self_value == other_value,
+            (
+                Param::String(self_value),
+                Expr::String(other_value)
+                | Expr::StrRef(other_value)
+                | Expr::SafeString(other_value),
+            ) => self_value == other_value,
+
+            // Literal values, matched by what the argument expands to
+            (
+                Param::Integer(_) | Param::String(_),
+                Expr::PathIdent(_)
+                | Expr::MappingApplication(_)
+                | Expr::TemplateString(_)
+                | Expr::Is(_),
+            ) => match (param, self.args.value(arg)) {
+                (Param::Integer(self_value), Some(Expanded::Int(value))) => *self_value == value,
+                (Param::String(self_value), Some(Expanded::Str(value) | Expanded::Safe(value))) => {
+                    *self_value == value
+                }
+                _ => false,
             },
-            Expr::MappingApplication(appl),
-        ) if appl.args.is_empty() => true, // In Expr::parse, idents are also parsed as mapping
-        // applications
-
-        // Raw literal matches
-        (Param::Ident(self_value), Expr::PathIdent(other_value)) => self_value == other_value,
-        (Param::Ident(self_value), Expr::MappingApplication(MappingApplication { name, args }))
-            if args.is_empty() =>
-        {
-            self_value == name
+
+            // Structural patterns
+            (Param::Application { name, params }, Expr::MappingApplication(appl)) => {
+                name == &appl.name && self.matches_args(params, &appl.args)
+            }
+            (Param::Block(params), Expr::Block(block)) => self.matches_args(params, &block.exprs),
+
+            _ => false,
         }
-        (Param::Symbol(self_value), Expr::LiteralSymbol(other_value)) => self_value == other_value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cmp::Ordering, collections::HashMap};
+
+    use expandr_syntax::{
+        ast::{
+            mapping::{Mapping, NamedArg},
+            Expr, PathIdent,
+        },
+        source_type::Owned,
+        ProgramContext, Symbol,
+    };
+
+    use reblessive::Stack;
+
+    use super::{compare_specificity, params_of, ArgValues};
+    use crate::context::{get_owned_context, EvaluationContext as _, ScopedContext};
+    use crate::expansion_error::ExpansionError;
+
+    fn context(definitions: &str) -> ProgramContext<Owned> {
+        get_owned_context(expandr_syntax::parse(definitions, None).unwrap().ctx)
+    }
+
+    /// Index of the overload of `name` the arguments resolve to, looked up like an application
+    /// does, expanding the arguments lookup asks for
+    fn resolved(
+        ctx: &ProgramContext<Owned>,
+        name: &str,
+        args: &[Expr<Owned>],
+        named: &[(&str, Expr<Owned>)],
+    ) -> Result<Option<usize>, ExpansionError> {
+        let name = PathIdent::from_str(name);
+        let overloads = &ctx[&name.name()];
+        let named: Vec<_> = (named.iter())
+            .map(|(name, value)| NamedArg {
+                name: Symbol::intern(name),
+                value: value.clone(),
+            })
+            .collect();
+        let args = ArgValues::new(args, ctx).with_named(&named);
+        let found = Stack::new()
+            .enter(|stk| args.resolve(stk, &name))
+            .finish()?;
+        Ok(found.map(|(m, _)| overloads.iter().position(|o| std::ptr::eq(o, m)).unwrap()))
+    }
+
+    fn ident(name: &str) -> Expr<Owned> {
+        Expr::PathIdent(PathIdent::from_str(name))
+    }
+
+    fn string(s: &str) -> Expr<Owned> {
+        Expr::String(s.to_string())
+    }
+
+    #[test]
+    fn specificity() {
+        let compare = |a: &str, b: &str| {
+            let (a, b) = (context(a), context(b));
+            let (a, b) = (a.values().next().unwrap(), b.values().next().unwrap());
+            compare_specificity(
+                params_of(a.iter().next().unwrap()),
+                params_of(b.iter().next().unwrap()),
+            )
+        };
+        let greater = Some(Ordering::Greater);
+
+        assert_eq!(
+            compare("map f a => \"\"", "map f [x:ident] => \"\""),
+            greater
+        );
+        assert_eq!(
+            compare("map f [x:ident] => \"\"", "map f [x] => \"\""),
+            greater
+        );
+        assert_eq!(
+            compare("map f [add [a] 0] => \"\"", "map f [add [a] [b]] => \"\""),
+            greater
+        );
+        assert_eq!(
+            compare("map f [a] => \"\"", "map f [b] => \"\""),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(compare("map f [a] 0 => \"\"", "map f 0 [b] => \"\""), None);
+    }
+
+    #[test]
+    fn repeated() {
+        let ctx = context("map f [x*] => \"\"\n");
+        assert_eq!(resolved(&ctx, "f", &[Expr::Integer(1)], &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn locals() {
+        let ctx = context("");
+        let scope = ScopedContext {
+            parent: &ctx,
+            locals: HashMap::from([(
+                Symbol::intern("p"),
+                vec![Mapping::SimpleMapping(Expr::Integer(1))],
+            )]),
+            lazy_locals: HashMap::new(),
+        };
+        let p = PathIdent::from_str("p");
+        let named = [NamedArg {
+            name: Symbol::intern("style"),
+            value: string("x"),
+        }];
+
+        // Locals don't take arguments, the lookup goes on in the parent scope
+        let args = ArgValues::new(&[], &scope);
+        assert!(scope.lookup(&p, &args).unwrap().is_some());
+        let args = ArgValues::new(&[Expr::Integer(2)], &scope);
+        assert!(scope.lookup(&p, &args).unwrap().is_none());
+        let args = ArgValues::new(&[], &scope).with_named(&named);
+        assert!(scope.lookup(&p, &args).unwrap().is_none());
+    }
+
+    #[test]
+    fn ambiguous() {
+        let ctx = context("map f [a] 0 => \"\"\nmap f 0 [b] => \"\"\nmap f 0 0 => \"\"\n");
+
+        // The literals are more specific than both captures
+        let (zero, one) = (Expr::Integer(0), Expr::Integer(1));
+        assert_eq!(
+            resolved(&ctx, "f", &[zero.clone(), zero.clone()], &[]).unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            resolved(&ctx, "f", &[one, zero.clone()], &[]).unwrap(),
+            Some(0)
+        );
+
+        let ctx = context("map g [a] 0 => \"\"\nmap g 0 [b] => \"\"\n");
+        let Err(ExpansionError::AmbiguousMapping { definitions, .. }) =
+            resolved(&ctx, "g", &[zero.clone(), zero], &[])
+        else {
+            panic!("Expected an ambiguity");
+        };
+        assert_eq!(definitions, ["map g [a] 0 (1:1)", "map g 0 [b] (2:1)"]);
+    }
+
+    #[test]
+    fn typed() {
+        let ctx = context(
+            "map size [n:int] => \"int\"\nmap size [s:str] => \"str\"\nmap three => [calc 1 + 2]\n",
+        );
+
+        assert_eq!(
+            resolved(&ctx, "size", &[Expr::Integer(3)], &[]).unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            resolved(&ctx, "size", &[string("3")], &[]).unwrap(),
+            Some(1)
+        );
+        // Arguments are expanded to match them
+        assert_eq!(
+            resolved(&ctx, "size", &[ident("three")], &[]).unwrap(),
+            Some(0)
+        );
+
+        let Err(ExpansionError::TypeMismatch {
+            position,
+            found,
+            expected,
+            ..
+        }) = resolved(&ctx, "size", &[Expr::LiteralSymbol('+')], &[])
+        else {
+            panic!("Expected a type mismatch");
+        };
+        assert_eq!(position, 1);
+        assert_eq!(&*found, "the symbol `+`");
+        assert_eq!(expected, ["`[n:int]`", "`[s:str]`"]);
+    }
+
+    #[test]
+    fn literal_values() {
+        let ctx = context(
+            "map fib 0 => \"zero\"\nmap fib [n:int] => \"n\"\nmap fib \"x\" => \"x\"\nmap one => [1]\nmap zero => [calc 1 - 1]\n",
+        );
+
+        assert_eq!(
+            resolved(&ctx, "fib", &[Expr::Integer(0)], &[]).unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            resolved(&ctx, "fib", &[Expr::Integer(1)], &[]).unwrap(),
+            Some(1)
+        );
+        assert_eq!(resolved(&ctx, "fib", &[string("x")], &[]).unwrap(), Some(2));
+        // Arguments match literals by their value
+        assert_eq!(
+            resolved(&ctx, "fib", &[ident("zero")], &[]).unwrap(),
+            Some(0)
+        );
+        assert_eq!(
+            resolved(&ctx, "fib", &[ident("one")], &[]).unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn guards() {
+        let ctx = context(
+            "map reg [r:/[a-d]x/] => \"reg\"\nmap reg [n:int where n > 0] => \"imm\"\nmap reg [n:int] => \"zero\"\n",
+        );
+        let params: Vec<_> = (ctx[&Symbol::intern("reg")].iter())
+            .map(|m| params_of(m).to_string())
+            .collect();
+        assert_eq!(params, ["[r:/[a-d]x/]", "[n:int where n > 0]", "[n:int]"]);
+
+        assert_eq!(resolved(&ctx, "reg", &[ident("bx")], &[]).unwrap(), Some(0));
+        assert_eq!(
+            resolved(&ctx, "reg", &[Expr::Integer(3)], &[]).unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            resolved(&ctx, "reg", &[Expr::Integer(0)], &[]).unwrap(),
+            Some(2)
+        );
+        let Err(ExpansionError::TypeMismatch { found, .. }) =
+            resolved(&ctx, "reg", &[ident("ex")], &[])
+        else {
+            panic!("Expected the register to be rejected");
+        };
+        assert_eq!(&*found, "the ident `ex`");
+
+        // Conditions are calculated by the builtin, also where `calc` is a mapping
+        let ctx =
+            context("map calc [a] [op:sym] [b] => [0]\nmap pos [n:int where n > 0] => \"pos\"\n");
+        assert_eq!(
+            resolved(&ctx, "pos", &[Expr::Integer(3)], &[]).unwrap(),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn defaults() {
+        let ctx = context(
+            "map size [n:int] => \"n\"\nmap size [n:int] [unit=\"px\"] => \"unit\"\nmap size [w=0] [h=0] [d] => \"box\"\n",
+        );
+        let overloads = &ctx[&Symbol::intern("size")];
+        let params: Vec<_> = overloads.iter().map(|m| params_of(m).to_string()).collect();
+        assert_eq!(
+            params,
+            ["[n:int]", "[n:int] [unit=\"px\"]", "[w=0] [h=0] [d]"]
+        );
+        // Where the arguments go in overload `i`
+        let arranged = |i: usize, args: &[Expr<Owned>], named: &[(&str, Expr<Owned>)]| {
+            let named: Vec<_> = (named.iter())
+                .map(|(name, value)| NamedArg {
+                    name: Symbol::intern(name),
+                    value: value.clone(),
+                })
+                .collect();
+            let params = params_of(overloads.iter().nth(i).unwrap());
+            ArgValues::new(args, &ctx)
+                .with_named(&named)
+                .arrange(params)
+        };
+        let three = [Expr::Integer(3)];
+
+        // Fewer defaults are more specific
+        assert_eq!(resolved(&ctx, "size", &three, &[]).unwrap(), Some(0));
+        assert_eq!(arranged(1, &three, &[]), Some(vec![Some(0), None]));
+        assert_eq!(arranged(2, &three, &[]), Some(vec![None, None, Some(0)]));
+
+        // Optional parameters are filled from the left
+        let args = [Expr::Integer(3), string("em")];
+        assert_eq!(resolved(&ctx, "size", &args, &[]).unwrap(), Some(1));
+        assert_eq!(arranged(2, &args, &[]), Some(vec![Some(0), None, Some(1)]));
+
+        // Named arguments are bound first
+        let named = [("unit", string("%"))];
+        assert_eq!(resolved(&ctx, "size", &three, &named).unwrap(), Some(1));
+        assert_eq!(arranged(1, &three, &named), Some(vec![Some(0), Some(1)]));
+        let args = [Expr::Integer(1), Expr::Integer(2)];
+        assert_eq!(
+            arranged(2, &args, &[("w", string("x"))]),
+            Some(vec![Some(2), Some(0), Some(1)])
+        );
 
-        _ => false,
+        // Unknown names don't match
+        let named = [("depth", Expr::Integer(1))];
+        assert_eq!(resolved(&ctx, "size", &three, &named).unwrap(), None);
+        assert!((0..3).all(|i| arranged(i, &three, &named).is_none()));
     }
 }
@@ -0,0 +1,245 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+};
+
+use expandr_syntax::{
+    ast::{
+        mapping::{Param, ParamType, ParameterizedMapping, Params},
+        PathIdent,
+    },
+    source_type::Owned,
+    Symbol,
+};
+
+use crate::{context::ExpansionOptions, expand::Expanded, expansion_error::ExpansionResult};
+
+/// Results of mapping applications, shared by the whole expansion of a file.
+///
+/// Applications are cached by the resolved definition, the expanded arguments and the expansion
+/// options. The translation of a memoized mapping must therefore only depend on its arguments.
+/// Results that applied a builtin which isn't pure are not cached, see `builtins::is_pure`.
+/// Mappings with lazy parameters expand expressions of the caller and are never memoized.
+#[derive(Default)]
+pub struct MemoCache {
+    /// Memoize all mappings, not only the ones in `names`
+    all: bool,
+    names: HashSet<Symbol>,
+
+    entries: RefCell<HashMap<MemoKey, Expanded>>,
+    hits: Cell<usize>,
+    misses: Cell<usize>,
+    /// Whether an impure builtin was applied in the expansion currently cached
+    impure: Cell<bool>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+pub struct MemoKey {
+    /// Address of the definition, which lives as long as the expansion
+    mapping: usize,
+    args: Vec<MemoValue>,
+    options: ExpansionOptions,
+}
+
+/// `Expanded` with strict equality, `1` and `'1'` are different arguments
+#[derive(PartialEq, Eq, Hash)]
+enum MemoValue {
+    Str(String),
+    Int(i64),
+    Safe(String),
+}
+
+impl From<Expanded> for MemoValue {
+    fn from(value: Expanded) -> Self {
+        match value {
+            Expanded::Str(s) => Self::Str(s),
+            Expanded::Int(i) => Self::Int(i),
+            Expanded::Safe(s) => Self::Safe(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub entries: usize,
+}
+
+impl fmt::Display for MemoStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} cache hits, {} misses, {} cached results",
+            self.hits, self.misses, self.entries
+        )
+    }
+}
+
+impl MemoCache {
+    /// Memoize all mappings if `all` is set, only the ones in `names` otherwise
+    pub fn new(all: bool, names: impl IntoIterator<Item = Symbol>) -> Self {
+        Self {
+            all,
+            names: names.into_iter().collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.all || !self.names.is_empty()
+    }
+
+    /// Whether applications of the definition are cached
+    pub fn memoizes(&self, name: &PathIdent, mapping: &ParameterizedMapping<Owned>) -> bool {
+        (self.all || self.names.contains(&name.canonical())) && !has_lazy_params(&mapping.params)
+    }
+
+    pub fn key(
+        mapping: &ParameterizedMapping<Owned>,
+        args: Vec<Expanded>,
+        options: ExpansionOptions,
+    ) -> MemoKey {
+        MemoKey {
+            mapping: mapping as *const _ as usize,
+            args: args.into_iter().map(MemoValue::from).collect(),
+            options,
+        }
+    }
+
+    /// The cached result for `key`, or the result of `expand` which is cached if it succeeds
+    /// without applying an impure builtin. `expand` is only awaited on a miss.
+    pub async fn get_or_expand(
+        &self,
+        key: MemoKey,
+        expand: impl Future<Output = ExpansionResult>,
+    ) -> ExpansionResult {
+        if let Some(value) = self.entries.borrow().get(&key) {
+            self.hits.set(self.hits.get() + 1);
+            return Ok(value.clone());
+        }
+        self.misses.set(self.misses.get() + 1);
+
+        // The borrow must not be held while expanding, nested applications use the cache as well
+        let outer_impure = self.impure.replace(false);
+        let value = expand.await;
+        let impure = self.impure.get();
+        // Applications around this one depend on the impure builtin as well
+        self.impure.set(outer_impure || impure);
+
+        let value = value?;
+        if !impure {
+            self.entries.borrow_mut().insert(key, value.clone());
+        }
+        Ok(value)
+    }
+
+    /// Record that a builtin which isn't pure was applied, so that the applications it was
+    /// applied in aren't cached
+    pub fn mark_impure(&self) {
+        self.impure.set(true);
+    }
+
+    pub fn stats(&self) -> MemoStats {
+        MemoStats {
+            hits: self.hits.get(),
+            misses: self.misses.get(),
+            entries: self.entries.borrow().len(),
+        }
+    }
+}
+
+fn has_lazy_params(params: &Params) -> bool {
+    params.entries.iter().any(|param| match param {
+        Param::ParamExpr { typ, .. } => matches!(typ, ParamType::Lazy),
+        Param::Application { params, .. } | Param::Block(params) => has_lazy_params(params),
+        Param::Symbol(_)
+        | Param::Operator(_)
+        | Param::Ident(_)
+        | Param::Integer(_)
+        | Param::String(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use reblessive::Stack;
+
+    use super::{MemoCache, MemoKey};
+    use crate::{
+        context::{get_owned_context, ExpansionOptions, MemoContext},
+        expand::{Expandable as _, Expanded},
+    };
+
+    fn key(args: &[i64]) -> MemoKey {
+        MemoKey {
+            mapping: 0,
+            args: args.iter().map(|&i| Expanded::Int(i).into()).collect(),
+            options: ExpansionOptions::default(),
+        }
+    }
+
+    #[test]
+    fn impure() {
+        let memo = MemoCache::new(true, []);
+        let mut stack = Stack::new();
+
+        // Expanding an application that applies an impure builtin
+        stack
+            .enter(|_| {
+                memo.get_or_expand(key(&[1]), async {
+                    memo.mark_impure();
+                    Ok(Expanded::Int(1))
+                })
+            })
+            .finish()
+            .unwrap();
+        assert_eq!(memo.stats().entries, 0);
+
+        // Also makes the applications around it impure
+        stack
+            .enter(|_| {
+                memo.get_or_expand(key(&[2]), async {
+                    let inner = memo.get_or_expand(key(&[3]), async {
+                        memo.mark_impure();
+                        Ok(Expanded::Int(3))
+                    });
+                    inner.await
+                })
+            })
+            .finish()
+            .unwrap();
+        assert_eq!(memo.stats().entries, 0);
+
+        // But not the ones after it
+        stack
+            .enter(|_| memo.get_or_expand(key(&[4]), async { Ok(Expanded::Int(4)) }))
+            .finish()
+            .unwrap();
+        assert_eq!(memo.stats().entries, 1);
+    }
+
+    /// Memoized mappings don't see the parameters of their callers, which aren't part of the key
+    #[test]
+    fn global_scope() {
+        let src = "\
+memoize get
+map x => 'global'
+map get [y] => [x]
+map a [x] => [get 1]
+map b [x] => [get 1]
+[a 'A'] [b 'B']
+";
+        let ast = expandr_syntax::parse(src, None).unwrap();
+        let ctx = get_owned_context(ast.ctx.clone());
+        let memo = MemoCache::new(false, ast.memoize.iter().copied());
+        let value = ast.expand(&MemoContext {
+            inner: &ctx,
+            cache: &memo,
+        });
+        assert_eq!(value.unwrap(), Expanded::Str("globalglobal".to_string()));
+        assert_eq!(memo.stats().hits, 1);
+    }
+}
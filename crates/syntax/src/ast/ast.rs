@@ -1,12 +1,14 @@
+use std::ops::RangeInclusive;
+
 use crate::{
     ast::mapping::Mapping,
     errors::parse_error::ParseResult,
     lexer::{ExprToken, RawToken, Token},
     log,
     parser::TokenizationMode,
-    program_context::ProgramContext,
+    program_context::{Origin, ProgramContext},
     source_type::{Borrowed, SourceType},
-    unexpected_token,
+    unexpected_token, Symbol,
 };
 
 use super::*;
@@ -16,6 +18,15 @@ pub struct Ast<S: SourceType> {
     pub exprs: Vec<Expr<S>>,
     pub imports: Vec<Import>,
     pub ctx: ProgramContext<S>,
+
+    /// Escaping mode of the `autoescape <mode>` directive
+    pub autoescape: Option<String>,
+
+    /// Whether the file has a `reindent` directive
+    pub reindent: bool,
+
+    /// Names of the mappings in `memoize <name>...` directives
+    pub memoize: Vec<Symbol>,
 }
 
 impl<'s> Parsable<'s> for Ast<Borrowed<'s>> {
@@ -23,6 +34,9 @@ impl<'s> Parsable<'s> for Ast<Borrowed<'s>> {
         let mut ctx = ProgramContext::new();
         let mut imports = Vec::new();
         let mut exprs = Vec::new();
+        let mut autoescape = None;
+        let mut reindent = false;
+        let mut memoize = Vec::new();
 
         loop {
             parser.skip_newlines();
@@ -36,18 +50,21 @@ impl<'s> Parsable<'s> for Ast<Borrowed<'s>> {
                     imports.push(Import::parse(parser)?);
                 }
                 ExprToken::Map => {
+                    let map_ctx = parser.ctx();
+                    let origin = Origin {
+                        source_name: map_ctx.source_name.as_deref().map(Symbol::intern),
+                        line: map_ctx.line,
+                        column: map_ctx.token_start(),
+                    };
                     parser.advance();
                     let Some(Token::ExprToken(ExprToken::Ident(name))) = parser.current()? else {
                         panic!("Expecting ident after keyword 'map'");
                     };
                     parser.advance();
                     let mapping = Mapping::parse(parser)?;
-                    match ctx.get_mut(name) {
-                        Some(slot) => slot.push(mapping),
-                        None => {
-                            let _ = ctx.insert(name, vec![mapping]);
-                        }
-                    }
+                    ctx.entry(Symbol::intern(name))
+                        .or_default()
+                        .push(mapping, origin);
                 }
                 ExprToken::Symbol('[') => {
                     parser.advance();
@@ -68,6 +85,22 @@ impl<'s> Parsable<'s> for Ast<Borrowed<'s>> {
                     );
                     parser.advance();
                 }
+                ExprToken::Ident(keyword @ ("autoescape" | "reindent" | "dedent" | "memoize")) => {
+                    let arity = match keyword {
+                        "autoescape" => 1..=1,
+                        "memoize" => 1..=usize::MAX,
+                        _ => 0..=0,
+                    };
+                    match directive(parser, arity)? {
+                        Some(args) => match keyword {
+                            "autoescape" => autoescape = Some(args[0].to_string()),
+                            "reindent" => reindent = true,
+                            "dedent" => parser.dedent = true,
+                            _ => memoize.extend(args.into_iter().map(Symbol::intern)),
+                        },
+                        None => exprs.push(Expr::parse(parser, TokenizationMode::Expr)?),
+                    }
+                }
                 ExprToken::Ident(_) => {
                     exprs.push(Expr::parse(parser, TokenizationMode::Expr)?);
                 }
@@ -83,6 +116,57 @@ impl<'s> Parsable<'s> for Ast<Borrowed<'s>> {
             exprs,
             imports,
             ctx,
+            autoescape,
+            reindent,
+            memoize,
         })
     }
 }
+
+/// Directives are a keyword and `arity` idents alone on their line, like `autoescape html`. The
+/// keywords are soft, anything else starting with them is an application (`reindent 'text'`).
+/// Consumes the directive and returns its idents, or `None` without consuming anything.
+fn directive<'s>(
+    parser: &mut Parser<'s>,
+    arity: RangeInclusive<usize>,
+) -> ParseResult<'s, Option<Vec<&'s str>>> {
+    let mut lookahead = parser.clone();
+    lookahead.ignore_newlines(false);
+    lookahead.advance();
+    let mut args = Vec::new();
+    while let Some(ExprToken::Ident(arg)) = lookahead.current_expr()? {
+        args.push(arg);
+        lookahead.advance();
+    }
+    if !arity.contains(&args.len())
+        || !matches!(lookahead.current_expr()?, Some(ExprToken::Newline) | None)
+    {
+        return Ok(None);
+    }
+    lookahead.ignore_newlines(true);
+    *parser = lookahead;
+    Ok(Some(args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soft_directives() {
+        let ast = crate::parse("memoize fib\nautoescape html\ndedent\n", None).unwrap();
+        assert_eq!(ast.memoize, [Symbol::intern("fib")]);
+        assert_eq!(ast.autoescape.as_deref(), Some("html"));
+        assert!(ast.exprs.is_empty());
+
+        // Applications of mappings with the name of a directive
+        for src in ["memoize 'x'", "memoize", "autoescape", "reindent 'x'"] {
+            let ast = crate::parse(src, None).unwrap();
+            assert!(ast.memoize.is_empty() && ast.autoescape.is_none() && !ast.reindent);
+            assert!(
+                matches!(&ast.exprs[..], [Expr::MappingApplication(_)]),
+                "{src:?} should be an application"
+            );
+        }
+    }
+}
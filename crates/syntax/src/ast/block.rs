@@ -1,5 +1,5 @@
 use crate::{
-    ast::Expr,
+    ast::{mapping::MappingApplication, Expr},
     errors::parse_error::ParseResult,
     lexer::ExprToken,
     parser::{Parsable, Parser, TokenizationMode},
@@ -9,6 +9,59 @@ use crate::{
 #[derive(Debug, Clone)]
 pub struct Block<S: SourceType> {
     pub exprs: Vec<Expr<S>>,
+    pub format: BlockFormat<S>,
+}
+
+/// How the expanded expressions of a block are joined, set with options right after the `[..`:
+/// `[.. sep ', ' prefix '(' suffix ')' trailing`
+///
+/// Without a separator, every expression is followed by a newline. With one, the separator is only
+/// put between expressions unless `trailing` is given.
+#[derive(Debug, Clone)]
+pub struct BlockFormat<S: SourceType> {
+    pub separator: Option<Box<Expr<S>>>,
+    pub prefix: Option<Box<Expr<S>>>,
+    pub suffix: Option<Box<Expr<S>>>,
+    pub trailing: bool,
+}
+
+impl<S: SourceType> Default for BlockFormat<S> {
+    fn default() -> Self {
+        Self {
+            separator: None,
+            prefix: None,
+            suffix: None,
+            trailing: true,
+        }
+    }
+}
+
+impl<'s> BlockFormat<Borrowed<'s>> {
+    /// Parse the options on the line of the `[..`. They are soft keywords, so a block starting with
+    /// an application of a mapping named like one of them has to bracket it: `[.. [sep x] ..]`
+    fn parse(parser: &mut Parser<'s>) -> ParseResult<'s, Self> {
+        let mut format = Self::default();
+        let mut trailing = false;
+
+        while let Some(ExprToken::Ident(option @ ("sep" | "prefix" | "suffix" | "trailing"))) =
+            parser.current_expr()?
+        {
+            parser.advance();
+            let target = match option {
+                "sep" => &mut format.separator,
+                "prefix" => &mut format.prefix,
+                "suffix" => &mut format.suffix,
+                _ => {
+                    trailing = true;
+                    continue;
+                }
+            };
+            *target = Some(Box::new(MappingApplication::parse_arg(parser)?));
+        }
+
+        format.trailing = trailing || format.separator.is_none();
+        Ok(format)
+    }
 }
 
 impl<'s> Parsable<'s> for Block<Borrowed<'s>> {
@@ -16,9 +69,10 @@ impl<'s> Parsable<'s> for Block<Borrowed<'s>> {
     fn parse(parser: &mut Parser<'s>) -> ParseResult<'s, Self> {
         let mut exprs = Vec::new();
 
-        // `[..`
-        parser.skip(ExprToken::BlockStart, file!(), line!())?;
+        // `[..`, newlines are significant from here on to tell the options apart
         parser.ignore_newlines(false);
+        parser.skip(ExprToken::BlockStart, file!(), line!())?;
+        let format = BlockFormat::parse(parser)?;
 
         loop {
             parser.skip_newlines();
@@ -33,6 +87,6 @@ impl<'s> Parsable<'s> for Block<Borrowed<'s>> {
         parser.skip(ExprToken::BlockEnd, file!(), line!())?;
         parser.ignore_newlines(true);
 
-        Ok(Self { exprs })
+        Ok(Self { exprs, format })
     }
 }
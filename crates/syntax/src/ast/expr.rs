@@ -4,9 +4,9 @@ use crate::{
     errors::parse_error::ParseResult,
     lexer::RawToken,
     log,
-    parser::TokenizationMode,
+    parser::{TokenizationMode, OPERATOR_CHARS},
     source_type::{Borrowed, SourceType},
-    unexpected_token,
+    unexpected_token, Symbol,
 };
 
 use super::*;
@@ -18,20 +18,31 @@ pub enum Expr<S: SourceType> {
     StrRef(S::Str),
     TemplateString(TemplateString<S>),
     Integer(i64),
+    /// Text already escaped for the output, bound from values marked safe
+    SafeString(String),
 
     // Meta tokens
     PathIdent(PathIdent),
     LiteralSymbol(char),
+    /// Several symbols without space in between (`->`, `::`)
+    LiteralOperator(Symbol),
 
     // Compound expressions
     MappingApplication(MappingApplication<S>),
     Block(Block<S>),
+    Is(IsExpr<S>),
 }
 
 derive_from!(TemplateString for Expr where S: SourceType);
 derive_from!(MappingApplication for Expr where S: SourceType);
 derive_from!(Block for Expr where S: SourceType);
 
+impl<S: SourceType> From<IsExpr<S>> for Expr<S> {
+    fn from(s: IsExpr<S>) -> Self {
+        <Expr<S>>::Is(s)
+    }
+}
+
 impl<S: SourceType> From<PathIdent> for Expr<S> {
     fn from(s: PathIdent) -> Self {
         <Expr<S>>::PathIdent(s)
@@ -45,12 +56,15 @@ impl<S: SourceType> std::fmt::Debug for Expr<S> {
             Self::StrRef(s) => write!(f, "StrRef({s:?})"),
             Self::TemplateString(s) => s.fmt(f),
             Self::Integer(s) => s.fmt(f),
+            Self::SafeString(s) => write!(f, "SafeString({s:?})"),
 
             Self::PathIdent(s) => write!(f, "PathIdent({s:?})"),
             Self::LiteralSymbol(s) => write!(f, "Symbol '{s}'"),
+            Self::LiteralOperator(s) => write!(f, "Operator '{s}'"),
 
             Self::MappingApplication(m_app) => m_app.fmt(f),
             Self::Block(b) => b.fmt(f),
+            Self::Is(is) => is.fmt(f),
         }
     }
 }
@@ -61,9 +75,24 @@ impl<'s> Expr<Borrowed<'s>> {
         parser.skip_newlines();
         let expr = match parser.current_expr()?.expect("Expr::parse on no token") {
             ExprToken::Symbol(']') => todo!("Decide if and how to allow empty exprs"),
+            ExprToken::Ident("is") => {
+                // Soft keyword, rewind and parse as mapping application if no branches follow
+                let checkpoint = parser.clone();
+                parser.advance();
+                match IsExpr::parse(parser)? {
+                    Some(is_expr) => is_expr.into(),
+                    None => {
+                        *parser = checkpoint;
+                        MappingApplication::parse(parser)?.into()
+                    }
+                }
+            }
             ExprToken::Ident(_) | ExprToken::Symbol('.') => {
                 MappingApplication::parse(parser)?.into()
             }
+            ExprToken::Symbol(c) if OPERATOR_CHARS.contains(c) => {
+                MappingApplication::parse_operator(parser)?.into()
+            }
             ExprToken::BlockStart => Block::parse(parser)?.into(),
             ExprToken::TemplateStringDelimiter(n) => {
                 TemplateString::parse(parser, RawToken::TemplateStringDelimiter(n))?.into()
@@ -75,12 +104,14 @@ impl<'s> Expr<Borrowed<'s>> {
             }
             ExprToken::Symbol('[') => {
                 let was_ignoring_newlines = parser.ignoring_newlines;
+                let was_interpolating = std::mem::replace(&mut parser.in_interpolation, false);
                 parser.ignore_newlines(true);
 
                 parser.advance();
                 let expr = Expr::parse(parser, TokenizationMode::Expr)?;
 
                 parser.ignore_newlines(was_ignoring_newlines);
+                parser.in_interpolation = was_interpolating;
                 parser.skip(ExprToken::Symbol(']'), file!(), line!())?;
 
                 expr
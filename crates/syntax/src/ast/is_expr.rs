@@ -0,0 +1,262 @@
+use crate::{
+    ast::{mapping::MappingApplication, Expr},
+    errors::parse_error::ParseResult,
+    lexer::ExprToken,
+    parser::Parser,
+    source_type::{Borrowed, SourceType},
+    unexpected_eof, unexpected_token, Symbol,
+};
+
+/// `is expr { .. pattern ? translation .. }`
+///
+/// `is` is a soft keyword: it only starts an `IsExpr` when the matched expression is followed by
+/// `{`, everywhere else it is an ordinary identifier.
+#[derive(Debug, Clone)]
+pub struct IsExpr<S: SourceType> {
+    pub expr: Box<Expr<S>>,
+    pub branches: Vec<IsBranch<S>>,
+}
+
+/// `.. alternative or alternative if guard ? translation`
+#[derive(Debug, Clone)]
+pub struct IsBranch<S: SourceType> {
+    pub alternatives: Vec<IsPattern<S>>,
+    pub guard: Option<Expr<S>>,
+    pub translation: Expr<S>,
+}
+
+#[derive(Debug, Clone)]
+pub enum IsPattern<S: SourceType> {
+    /// `_`
+    Wildcard,
+    /// Any expression, compared by value
+    Value(Expr<S>),
+    /// `a..b`, `a..=b`, `a..`
+    Range {
+        start: Expr<S>,
+        end: Option<Expr<S>>,
+        inclusive: bool,
+    },
+    /// `glob 'get_{name}*'`
+    Glob(Expr<S>),
+    /// `name @ pattern`
+    Binding {
+        name: Symbol,
+        pattern: Box<IsPattern<S>>,
+    },
+}
+
+impl<'s> IsExpr<Borrowed<'s>> {
+    /// Expects to start on the token after `is`. Returns `None` if no `{` follows the matched
+    /// expression, in which case the caller has to rewind and parse `is` as an identifier.
+    pub fn parse(parser: &mut Parser<'s>) -> ParseResult<'s, Option<Self>> {
+        match parser.current_expr()? {
+            None | Some(ExprToken::BlockEnd | ExprToken::Newline) => return Ok(None),
+            Some(ExprToken::Symbol(s)) if s != '[' => return Ok(None),
+            Some(_) => {}
+        }
+        let expr = Box::new(MappingApplication::parse_arg(parser)?);
+
+        if parser.current_expr()? != Some(ExprToken::Symbol('{')) {
+            return Ok(None);
+        }
+
+        // Branches may span multiple lines, also inside of blocks
+        let was_ignoring_newlines = parser.ignoring_newlines;
+        parser.ignore_newlines(true);
+        parser.advance();
+
+        let mut branches = Vec::new();
+        loop {
+            // Commas between branches are optional
+            if parser.current_expr()? == Some(ExprToken::Symbol(',')) {
+                parser.advance();
+            }
+            match parser.current_expr()? {
+                Some(ExprToken::Symbol('}')) => break,
+                None => unexpected_eof!(parser.ctx())?,
+                Some(_) => branches.push(IsBranch::parse(parser)?),
+            }
+        }
+
+        parser.ignore_newlines(was_ignoring_newlines);
+        parser.skip(ExprToken::Symbol('}'), file!(), line!())?;
+
+        Ok(Some(Self { expr, branches }))
+    }
+}
+
+impl<'s> IsBranch<Borrowed<'s>> {
+    fn parse(parser: &mut Parser<'s>) -> ParseResult<'s, Self> {
+        // Line mode branch marker
+        if parser.current_expr()? == Some(ExprToken::Symbol('.')) {
+            parser.advance();
+            parser.skip(ExprToken::Symbol('.'), file!(), line!())?;
+        }
+
+        let mut alternatives = vec![IsPattern::parse(parser)?];
+        while parser.current_expr()? == Some(ExprToken::Ident("or")) {
+            parser.advance();
+            alternatives.push(IsPattern::parse(parser)?);
+        }
+
+        let guard = match parser.current_expr()? {
+            Some(ExprToken::Ident("if")) => {
+                parser.advance();
+                Some(parse_operand(parser)?)
+            }
+            _ => None,
+        };
+
+        match parser.current_expr()? {
+            Some(ExprToken::Symbol('?')) => parser.advance(),
+            tok => unexpected_token!(
+                found: tok,
+                expected: [Ident("or"), Ident("if"), Symbol('?')],
+                @ parser.ctx()
+            )?,
+        }
+        let translation = parse_operand(parser)?;
+
+        Ok(Self {
+            alternatives,
+            guard,
+            translation,
+        })
+    }
+}
+
+impl<'s> IsPattern<Borrowed<'s>> {
+    fn parse(parser: &mut Parser<'s>) -> ParseResult<'s, Self> {
+        let first = match parser.current_expr()? {
+            Some(ExprToken::Ident("_")) => {
+                parser.advance();
+                return Ok(Self::Wildcard);
+            }
+            Some(ExprToken::Ident("glob")) => {
+                parser.advance();
+                return Ok(Self::Glob(parse_operand(parser)?));
+            }
+            _ => parse_operand(parser)?,
+        };
+
+        match parser.current_expr()? {
+            // Binding
+            Some(ExprToken::Symbol('@')) => {
+                let Expr::PathIdent(name) = first else {
+                    return unexpected_token!(
+                        found: ExprToken::Symbol('@'),
+                        expected: [Symbol('?'), Ident("or"), Ident("if")],
+                        @ parser.ctx()
+                    );
+                };
+                parser.advance();
+                Ok(Self::Binding {
+                    name: name.name(),
+                    pattern: Box::new(Self::parse(parser)?),
+                })
+            }
+
+            // Range
+            Some(ExprToken::Symbol('.')) => {
+                parser.advance();
+                parser.skip(ExprToken::Symbol('.'), file!(), line!())?;
+                let inclusive = parser.current_expr()? == Some(ExprToken::Symbol('='));
+                if inclusive {
+                    parser.advance();
+                }
+                let end = match parser.current_expr()? {
+                    // Inclusive ranges need an end
+                    Some(ExprToken::Symbol('?') | ExprToken::Ident("or" | "if")) if !inclusive => {
+                        None
+                    }
+                    _ => Some(parse_operand(parser)?),
+                };
+                Ok(Self::Range {
+                    start: first,
+                    end,
+                    inclusive,
+                })
+            }
+
+            _ => Ok(Self::Value(first)),
+        }
+    }
+}
+
+/// Like a mapping argument, but without literal symbols, which have no value to compare against
+fn parse_operand<'s>(parser: &mut Parser<'s>) -> ParseResult<'s, Expr<Borrowed<'s>>> {
+    match parser.current_expr()? {
+        Some(tok @ (ExprToken::Symbol(_) | ExprToken::BlockEnd))
+            if tok != ExprToken::Symbol('[') =>
+        {
+            unexpected_token!(
+                found: tok,
+                expected: [Ident, String, Integer, TemplateStringDelimiter, Symbol('['), BlockStart],
+                @ parser.ctx()
+            )
+        }
+        _ => MappingApplication::parse_arg(parser),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{errors::parse_error::ParseError, parser::TokenizationMode};
+
+    fn parse(src: &str) -> ParseResult<'_, Expr<Borrowed<'_>>> {
+        let mut parser = Parser::new(src, None, None);
+        Expr::parse(&mut parser, TokenizationMode::Expr)
+    }
+
+    #[test]
+    fn branches() {
+        let Ok(Expr::Is(is_expr)) = parse("[is x {\n .. 1 or 2 ? 'a'\n .. n @ 3.. if n ? 'b'\n}]")
+        else {
+            panic!("Expected an is expression");
+        };
+        assert_eq!(is_expr.branches.len(), 2);
+        assert_eq!(is_expr.branches[0].alternatives.len(), 2);
+        assert!(is_expr.branches[1].guard.is_some());
+        assert!(matches!(
+            &is_expr.branches[1].alternatives[0],
+            IsPattern::Binding { name, pattern }
+                if name.as_str() == "n" && matches!(**pattern, IsPattern::Range { end: None, .. })
+        ));
+    }
+
+    #[test]
+    fn soft_keyword() {
+        let Ok(Expr::MappingApplication(m_app)) = parse("[is x y]") else {
+            panic!("Expected a mapping application");
+        };
+        assert_eq!(m_app.name.name().as_str(), "is");
+        assert_eq!(m_app.args.len(), 2);
+
+        assert!(matches!(
+            parse("[is]"),
+            Ok(Expr::MappingApplication(m_app)) if m_app.args.is_empty()
+        ));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(
+            parse("[is x { .. 1 'a' }]"),
+            Err(ParseError::UnexpectedToken { .. })
+        ));
+        assert!(matches!(
+            parse("[is x { .. 1 ? ? }]"),
+            Err(ParseError::UnexpectedToken { .. })
+        ));
+        assert!(matches!(
+            parse("[is x { .. 1..= ? 'a' }]"),
+            Err(ParseError::UnexpectedToken { .. })
+        ));
+        assert!(matches!(
+            parse("[is x { .. 1 ? 'a'"),
+            Err(ParseError::UnexpectedEof { .. })
+        ));
+    }
+}
@@ -1,3 +1,10 @@
-use crate::ast::Expr;
+use crate::{ast::Expr, source_type::SourceType, Symbol};
 
 pub type Args<S> = Vec<Expr<S>>;
+
+/// Argument passed by the name of the parameter it binds to (`style='x'`)
+#[derive(Debug, Clone)]
+pub struct NamedArg<S: SourceType> {
+    pub name: Symbol,
+    pub value: Expr<S>,
+}
@@ -1,17 +1,19 @@
-use super::Args;
+use super::{Args, NamedArg};
 use crate::{
     ast::{Block, Expr, PathIdent, TemplateString},
     errors::parse_error::ParseResult,
     lexer::{ExprToken, RawToken, Token},
     parser::{Parsable as _, Parser, TokenizationMode},
     source_type::{Borrowed, SourceType},
-    unexpected_token,
+    unexpected_eof, unexpected_token, Symbol,
 };
 
 #[derive(Debug, Clone)]
 pub struct MappingApplication<S: SourceType> {
     pub name: PathIdent,
     pub args: Args<S>,
+    /// Arguments passed by name (`style='x'`), after the positional ones when bound
+    pub named: Vec<NamedArg<S>>,
 }
 
 impl<'s> MappingApplication<Borrowed<'s>> {
@@ -27,61 +29,185 @@ impl<'s> MappingApplication<Borrowed<'s>> {
 
         // (Only) Here we care about newlines (sometimes)
         let name = PathIdent::parse(parser)?;
+        let mut args = match name.canonical().as_str() {
+            "calc" => Self::parse_calc_args(parser)?,
+            _ => Self::parse_args(parser)?,
+        };
+        let mut named = Vec::new();
+        while let Some(ExprToken::Ident(arg_name)) = parser.current_expr()? {
+            if !Self::assignment_follows(parser) {
+                break;
+            }
+            parser.advance(); // name
+            parser.advance(); // '='
+            named.push(NamedArg {
+                name: Symbol::intern(arg_name),
+                value: Self::parse_arg(parser)?,
+            });
+            args.extend(Self::parse_args(parser)?);
+        }
+
+        Ok(Self { name, args, named })
+    }
 
+    /// Application named by an operator (`[== a b]`)
+    pub fn parse_operator(parser: &mut Parser<'s>) -> ParseResult<'s, Self> {
+        let name = PathIdent::from_str(parser.operator()?);
+        let args = Self::parse_args(parser)?;
+
+        Ok(Self {
+            name,
+            args,
+            named: Vec::new(),
+        })
+    }
+
+    /// Whether the current ident is directly followed by `=`, as in `style='x'`. Comparisons
+    /// (`a==b`) don't count.
+    fn assignment_follows(parser: &Parser<'s>) -> bool {
+        let rest = parser.rest();
+        rest.starts_with('=') && !rest.starts_with("==")
+    }
+
+    /// Parse arguments up to the end of the application (`]`, `..]` or a newline in blocks)
+    pub(crate) fn parse_args(parser: &mut Parser<'s>) -> ParseResult<'s, Args<Borrowed<'s>>> {
         let mut args = Vec::new();
-        while let Some(token) = parser.current_expr()? {
-            match token {
-                ExprToken::Newline => {
-                    // Used in block expressions
-                    parser.advance();
-                    break;
-                }
-                ExprToken::BlockEnd | ExprToken::Symbol(']') => {
-                    // Caller needs to advance
-                    break;
-                }
-                ExprToken::Symbol('[') => {
-                    parser.advance();
-                    args.push(Expr::parse(parser, TokenizationMode::Expr)?);
-                    parser.skip(ExprToken::Symbol(']'), file!(), line!())?;
-                }
-                ExprToken::Ident(value) => {
-                    args.push(PathIdent::from_str(value).into());
-                    parser.advance();
-                }
-                ExprToken::Symbol(s) => {
-                    args.push(Expr::LiteralSymbol(s));
-                    parser.advance();
-                }
-                ExprToken::String(value) => {
-                    args.push(Expr::StrRef(value));
-                    parser.advance();
-                }
-                ExprToken::BlockStart => args.push(Block::parse(parser)?.into()),
-                ExprToken::TemplateStringDelimiter(n) => {
-                    // Parse in raw mode until matching number of template string delimiters
-                    args.push(
-                        TemplateString::parse(parser, RawToken::TemplateStringDelimiter(n))?.into(),
-                    );
-                }
-                ExprToken::Integer(int) => {
-                    args.push(Expr::Integer(int));
-                    parser.advance();
-                }
-                tok => unexpected_token!(
-                    found: tok,
-                    expected: [
-                        Symbol(']' | '[' | '{'),
-                        Symbol(_),
-                        String,
-                        TemplateStringDelimiter,
-                        Ident,
-                        Maybe(Newline)
-                    ],
-                    @parser.ctx()
-                )?,
-            };
+        while Self::arg_follows(parser)? {
+            args.push(Self::parse_arg(parser)?);
         }
-        Ok(Self { name, args })
+        Ok(args)
+    }
+
+    /// Arguments of `calc`. Nested groups starting with an integer (`[calc [2 + 3] * 4]`) are
+    /// parsed as the arguments in between, enclosed in `(` and `)`.
+    pub(crate) fn parse_calc_args(parser: &mut Parser<'s>) -> ParseResult<'s, Args<Borrowed<'s>>> {
+        let mut args = Vec::new();
+        while Self::arg_follows(parser)? {
+            if !Self::calc_group_follows(parser)? {
+                args.push(Self::parse_arg(parser)?);
+                continue;
+            }
+            let was_ignoring_newlines = parser.ignoring_newlines;
+            let was_interpolating = std::mem::replace(&mut parser.in_interpolation, false);
+            parser.ignore_newlines(true);
+            parser.advance();
+
+            args.push(Expr::LiteralSymbol('('));
+            args.extend(Self::parse_calc_args(parser)?);
+            args.push(Expr::LiteralSymbol(')'));
+
+            parser.ignore_newlines(was_ignoring_newlines);
+            parser.in_interpolation = was_interpolating;
+            parser.skip(ExprToken::Symbol(']'), file!(), line!())?;
+        }
+        Ok(args)
+    }
+
+    /// Whether the current token is `[` and an integer follows
+    fn calc_group_follows(parser: &Parser<'s>) -> ParseResult<'s, bool> {
+        if parser.current_expr()? != Some(ExprToken::Symbol('[')) {
+            return Ok(false);
+        }
+        let mut ahead = parser.clone();
+        ahead.advance();
+        Ok(matches!(ahead.current_expr()?, Some(ExprToken::Integer(_))))
+    }
+
+    /// Whether another argument follows. The newline ending an application in a block is skipped.
+    fn arg_follows(parser: &mut Parser<'s>) -> ParseResult<'s, bool> {
+        Ok(match parser.current_expr()? {
+            None => false,
+            Some(ExprToken::Newline) => {
+                // Used in block expressions
+                parser.advance();
+                false
+            }
+            // Caller needs to advance
+            Some(ExprToken::BlockEnd | ExprToken::Symbol(']')) => false,
+            // `-]` in template strings
+            Some(ExprToken::Symbol('-')) if parser.trim_marker_follows()? => false,
+            // Named argument, only taken by `MappingApplication::parse`
+            Some(ExprToken::Ident(_)) if Self::assignment_follows(parser) => false,
+            Some(_) => true,
+        })
+    }
+
+    /// Parse a single argument
+    pub(crate) fn parse_arg(parser: &mut Parser<'s>) -> ParseResult<'s, Expr<Borrowed<'s>>> {
+        let Some(token) = parser.current_expr()? else {
+            unexpected_eof!(parser.ctx())?
+        };
+        Ok(match token {
+            ExprToken::Symbol('[') => {
+                let was_interpolating = std::mem::replace(&mut parser.in_interpolation, false);
+                parser.advance();
+                let expr = Expr::parse(parser, TokenizationMode::Expr)?;
+                parser.in_interpolation = was_interpolating;
+                parser.skip(ExprToken::Symbol(']'), file!(), line!())?;
+                expr
+            }
+            ExprToken::Ident(value) => {
+                parser.advance();
+                PathIdent::from_str(value).into()
+            }
+            ExprToken::Symbol(s) => {
+                let operator = parser.operator()?;
+                if operator.len() == s.len_utf8() {
+                    Expr::LiteralSymbol(s)
+                } else {
+                    Expr::LiteralOperator(Symbol::intern(operator))
+                }
+            }
+            ExprToken::String(value) => {
+                parser.advance();
+                Expr::StrRef(value)
+            }
+            ExprToken::BlockStart => Block::parse(parser)?.into(),
+            ExprToken::TemplateStringDelimiter(n) => {
+                // Parse in raw mode until matching number of template string delimiters
+                TemplateString::parse(parser, RawToken::TemplateStringDelimiter(n))?.into()
+            }
+            ExprToken::Integer(int) => {
+                parser.advance();
+                Expr::Integer(int)
+            }
+            tok => unexpected_token!(
+                found: tok,
+                expected: [
+                    Symbol(']' | '[' | '{'),
+                    Symbol(_),
+                    String,
+                    TemplateStringDelimiter,
+                    Ident,
+                    Maybe(Newline)
+                ],
+                @parser.ctx()
+            )?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Expr;
+
+    /// Only `calc` reads nested groups starting with an integer
+    #[test]
+    fn calc_groups() {
+        let ast = crate::parse("[calc [2 + 3] * 4]", None).unwrap();
+        let [Expr::MappingApplication(calc)] = &ast.exprs[..] else {
+            panic!("Expected a single application, got {:?}", ast.exprs);
+        };
+        let args: Vec<_> = (calc.args.iter())
+            .map(|arg| match arg {
+                Expr::LiteralSymbol(c) => c.to_string(),
+                Expr::Integer(n) => n.to_string(),
+                arg => panic!("Unexpected argument {arg:?}"),
+            })
+            .collect();
+        assert_eq!(args, ["(", "2", "+", "3", ")", "*", "4"]);
+
+        assert!(crate::parse("[calc [x] * 4]", None).is_ok());
+        assert!(crate::parse("[f [2 + 3]]", None).is_err());
     }
 }
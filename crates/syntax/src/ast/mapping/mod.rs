@@ -5,9 +5,9 @@ mod param;
 mod params;
 
 pub use self::{
-    args::Args,
+    args::{Args, NamedArg},
     mapping_application::MappingApplication,
     mapping_definition::{Mapping, ParameterizedMapping},
-    param::{Param, ParamType},
+    param::{DefaultValue, Guard, Param, ParamType},
     params::Params,
 };
@@ -1,13 +1,63 @@
+use regex::Regex;
+
+use super::{params::Params, Args, MappingApplication};
 use crate::{
-    ast::{ExprToken, Parsable, Parser, PathIdent},
-    errors::parse_error::ParseResult,
-    unexpected_eof, unexpected_token,
+    ast::{Expr, ExprToken, Parsable, Parser, PathIdent},
+    errors::parse_error::{ParseError, ParseResult},
+    source_type::Owned,
+    unexpected_eof, unexpected_token, IntoOwned as _, Symbol,
 };
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ParamType {
     Expr,
     Ident,
+    /// Bound unevaluated, expanded in the caller's scope each time it is referenced
+    Lazy,
+    /// Arguments that expand to an integer
+    Int,
+    /// Arguments that expand to a string
+    Str,
+    /// Block arguments (`[.. ..]`), bound expanded
+    Block,
+    /// Literal symbols (`+`), bound as a string
+    Sym,
+}
+
+impl ParamType {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Expr => "expr",
+            Self::Ident => "ident",
+            Self::Lazy => "lazy",
+            Self::Int => "int",
+            Self::Str => "str",
+            Self::Block => "block",
+            Self::Sym => "sym",
+        }
+    }
+}
+
+/// Restricts the arguments a parameter accepts
+#[derive(Clone, Debug)]
+pub enum Guard {
+    /// `[reg:/[a-d]x/]`: The text of the argument has to match the whole pattern. Undefined
+    /// idents are matched by their name, other arguments by what they expand to.
+    Regex { pattern: String, regex: Regex },
+    /// `[n:int where n > 0]`: Has to expand to a truthy value, with only the parameter bound. A
+    /// condition of several arguments is a calculation, as in `calc`.
+    Where {
+        source: String,
+        condition: Args<Owned>,
+    },
+}
+
+/// Value of a parameter that no argument is passed for (`[style=""]`), expanded in the scope
+/// the mapping is defined in
+#[derive(Clone, Debug)]
+pub struct DefaultValue {
+    pub source: String,
+    pub value: Expr<Owned>,
 }
 
 #[derive(Clone)]
@@ -17,8 +67,24 @@ pub enum Param {
         name: PathIdent,
         rep: Option<Repetition>,
         typ: ParamType,
+        guard: Option<Guard>,
+        default: Option<Box<DefaultValue>>,
     },
     Symbol(char),
+    /// Several symbols without space in between (`->`, `::`)
+    Operator(Symbol),
+    /// Matches arguments that are or expand to this integer
+    Integer(i64),
+    /// Matches arguments that are or expand to this string (`"br"`)
+    String(String),
+
+    /// Destructures a nested mapping application (`[add [a] 0]`)
+    Application {
+        name: PathIdent,
+        params: Params,
+    },
+    /// Destructures a block, one parameter per line (`[.. [first] [second] ..]`)
+    Block(Params),
 }
 
 impl std::fmt::Debug for Param {
@@ -28,14 +94,86 @@ impl std::fmt::Debug for Param {
                 name,
                 typ,
                 rep: Some(rep),
+                ..
             } => {
                 write!(f, "Expr(name: {:?}, type: {:?}, rep: {rep:?})", name, typ)
             }
+            Self::ParamExpr {
+                name,
+                typ,
+                guard: Some(guard),
+                ..
+            } => {
+                write!(
+                    f,
+                    "Expr(name: `{}`, type: {:?}, guard: {guard:?})",
+                    name, typ
+                )
+            }
+            Self::ParamExpr {
+                name,
+                typ,
+                default: Some(default),
+                ..
+            } => {
+                write!(
+                    f,
+                    "Expr(name: `{}`, type: {:?}, default: {})",
+                    name, typ, default.source
+                )
+            }
             Self::ParamExpr { name, typ, .. } => {
-                write!(f, "Expr(name: `{}`, type: {:?})", name.to_string(), typ)
+                write!(f, "Expr(name: `{}`, type: {:?})", name, typ)
             }
             Self::Ident(ident) => write!(f, "Exactly('{}')", ident.original_src),
             Self::Symbol(c) => write!(f, "Exactly('{}')", c),
+            Self::Operator(op) => write!(f, "Exactly('{op}')"),
+            Self::Integer(n) => write!(f, "Exactly({n})"),
+            Self::String(s) => write!(f, "Exactly({s:?})"),
+            Self::Application { name, params } => write!(f, "Application({name}: {params:?})"),
+            Self::Block(params) => write!(f, "Block({params:?})"),
+        }
+    }
+}
+
+/// In the syntax of a definition
+impl std::fmt::Display for Param {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ident(ident) => write!(f, "{ident}"),
+            Self::ParamExpr {
+                name,
+                rep,
+                typ,
+                guard,
+                default,
+            } => {
+                write!(f, "[{name}")?;
+                match rep {
+                    Some(Repetition::Exactly(n)) => write!(f, "{{{n}}}")?,
+                    Some(Repetition::Optional) => f.write_str("?")?,
+                    Some(Repetition::Any) => f.write_str("*")?,
+                    None => {}
+                }
+                match (typ, guard) {
+                    (_, Some(Guard::Regex { pattern, .. })) => write!(f, ":/{pattern}/")?,
+                    (ParamType::Expr, _) => {}
+                    (typ, _) => write!(f, ":{}", typ.name())?,
+                }
+                if let Some(default) = default {
+                    write!(f, "={}", default.source)?;
+                }
+                if let Some(Guard::Where { source, .. }) = guard {
+                    write!(f, " where {source}")?;
+                }
+                f.write_str("]")
+            }
+            Self::Symbol(c) => write!(f, "{c}"),
+            Self::Operator(op) => write!(f, "{op}"),
+            Self::Integer(n) => write!(f, "{n}"),
+            Self::String(s) => write!(f, "\"{s}\""),
+            Self::Application { name, params } => write!(f, "[{name} {params}]"),
+            Self::Block(params) => write!(f, "[.. {params} ..]"),
         }
     }
 }
@@ -58,6 +196,19 @@ impl<'s> Parsable<'s> for Param {
                 };
                 let name = PathIdent::from_str(raw_ident);
                 parser.advance();
+
+                // Nested pattern (`[name params..]`)
+                if !matches!(
+                    parser.current_expr()?,
+                    Some(
+                        ExprToken::Symbol(']' | '*' | '?' | '{' | ':' | '=')
+                            | ExprToken::Ident("where")
+                    ) | None
+                ) {
+                    let params = Params::parse_until(parser, ExprToken::Symbol(']'))?;
+                    return Ok(Self::Application { name, params });
+                }
+
                 let rep = match parser.current_expr()? {
                     Some(ExprToken::Symbol('*')) => {
                         parser.advance();
@@ -72,7 +223,7 @@ impl<'s> Parsable<'s> for Param {
                         //let Some(Token::Number)
                         //Some(Repetition::Exactly(1))
                     }
-                    Some(ExprToken::Symbol(']' | ':')) => None,
+                    Some(ExprToken::Symbol(']' | ':' | '=') | ExprToken::Ident("where")) => None,
                     None => unexpected_eof!(parser.ctx())?,
                     tok => unexpected_token!(
                             found: tok,
@@ -81,47 +232,172 @@ impl<'s> Parsable<'s> for Param {
                     )?,
                 };
 
-                // Optionally a type (`[a:ident]`, `[a:expr]`)
-                let typ = if parser.current_expr()? == Some(ExprToken::Symbol(':')) {
+                // Optionally a type (`[a:ident]`, `[a:expr]`, `[a:lazy]`, `[a:int]`, ..) or a
+                // regex (`[a:/[a-d]x/]`)
+                let mut typ = ParamType::Expr;
+                let mut guard = None;
+                if parser.current_expr()? == Some(ExprToken::Symbol(':')) {
                     parser.advance(); // ':'
-                    let typ = match parser.current_expr()? {
-                        Some(ExprToken::Ident("ident")) => ParamType::Ident,
-                        Some(ExprToken::Ident("expr")) => ParamType::Expr,
-                        Some(ExprToken::Ident(ident)) => {
-                            panic!("Unknown param type specifier: {ident}")
+                    match parser.current_expr()? {
+                        // `/ax/` is lexed as an absolute path
+                        Some(ExprToken::Symbol('/')) => guard = Some(parse_regex(parser)?),
+                        Some(ExprToken::Ident(ident)) if ident.starts_with('/') => {
+                            guard = Some(parse_regex(parser)?)
                         }
-                        tok => unexpected_token!(
-                            found: tok,
-                            expected: [Ident],
-                            @ parser.ctx()
-                        )?,
-                    };
+                        tok => {
+                            typ = match tok {
+                                Some(ExprToken::Ident("ident")) => ParamType::Ident,
+                                Some(ExprToken::Ident("expr")) => ParamType::Expr,
+                                Some(ExprToken::Ident("lazy")) => ParamType::Lazy,
+                                Some(ExprToken::Ident("int")) => ParamType::Int,
+                                Some(ExprToken::Ident("str")) => ParamType::Str,
+                                Some(ExprToken::Ident("block")) => ParamType::Block,
+                                Some(ExprToken::Ident("sym")) => ParamType::Sym,
+                                tok => unexpected_token!(
+                                    found: tok,
+                                    expected: [
+                                        Ident("ident"),
+                                        Ident("expr"),
+                                        Ident("lazy"),
+                                        Ident("int"),
+                                        Ident("str"),
+                                        Ident("block"),
+                                        Ident("sym"),
+                                        Symbol('/'),
+                                    ],
+                                    @ parser.ctx()
+                                )?,
+                            };
+                            parser.advance();
+                        }
+                    }
+                }
+
+                // Optionally a default value (`[style=""]`, `[n:int=0]`)
+                let mut default = None;
+                if parser.current_expr()? == Some(ExprToken::Symbol('=')) {
                     parser.advance();
-                    typ
-                } else {
-                    ParamType::Expr
-                };
+                    let start = parser.position();
+                    let value = MappingApplication::parse_arg(parser)?;
+                    default = Some(Box::new(DefaultValue {
+                        source: parser.source_since(start).trim_end().to_string(),
+                        value: value.into_owned(),
+                    }));
+                }
+
+                // Optionally a condition (`[n:int where n > 0]`)
+                if guard.is_none() && parser.current_expr()? == Some(ExprToken::Ident("where")) {
+                    parser.advance();
+                    guard = Some(parse_condition(parser)?);
+                }
 
                 parser.skip(ExprToken::Symbol(']'), file!(), line!())?;
 
-                Ok(Self::ParamExpr { name, rep, typ })
+                Ok(Self::ParamExpr {
+                    name,
+                    rep,
+                    typ,
+                    guard,
+                    default,
+                })
             }
-            ExprToken::Symbol(s) if s != '[' => {
+            ExprToken::BlockStart => {
+                parser.advance();
+                Ok(Self::Block(Params::parse_until(
+                    parser,
+                    ExprToken::BlockEnd,
+                )?))
+            }
+            ExprToken::Integer(n) => {
+                parser.advance();
+                Ok(Self::Integer(n))
+            }
+            ExprToken::String(s) => {
                 parser.advance();
-                Ok(Self::Symbol(s))
+                Ok(Self::String(s.to_string()))
+            }
+            ExprToken::Symbol(s) if s != '[' => {
+                let operator = parser.operator()?;
+                if operator.len() == s.len_utf8() {
+                    Ok(Self::Symbol(s))
+                } else {
+                    Ok(Self::Operator(Symbol::intern(operator)))
+                }
             }
             tok => unexpected_token!(
                 found: tok,
-                expected: [Ident, Expr, Symbol],
+                expected: [Ident, Expr, Symbol, Integer, String, BlockStart],
                 @ parser.ctx()
             )?,
         }
     }
 }
 
+impl Param {
+    /// Whether calls can leave out the argument for this parameter
+    pub fn is_optional(&self) -> bool {
+        matches!(
+            self,
+            Self::ParamExpr {
+                default: Some(_),
+                ..
+            }
+        )
+    }
+}
+
+/// `/pattern/`, anchored to match whole arguments
+fn parse_regex<'s>(parser: &mut Parser<'s>) -> ParseResult<'s, Guard> {
+    let ctx = parser.ctx();
+    let pattern = parser.delimited('/')?;
+    let regex = Regex::new(&format!("^(?:{pattern})$")).map_err(|e| ParseError::InvalidRegex {
+        error: e.to_string(),
+        ctx,
+        file: file!(),
+        line: line!(),
+    })?;
+    Ok(Guard::Regex {
+        pattern: pattern.to_string(),
+        regex,
+    })
+}
+
+/// The condition after `where`, up to the closing `]`. Several arguments (`n > 0`) are read like
+/// the arguments of `calc`.
+fn parse_condition<'s>(parser: &mut Parser<'s>) -> ParseResult<'s, Guard> {
+    let start = parser.position();
+    let args = MappingApplication::parse_calc_args(parser)?;
+    let source = parser.source_since(start).trim_end().to_string();
+    if args.is_empty() {
+        unexpected_token!(found: parser.current_expr()?, expected: [Expr], @ parser.ctx())?;
+    }
+    Ok(Guard::Where {
+        source,
+        condition: args.into_iter().map(|arg| arg.into_owned()).collect(),
+    })
+}
+
 #[derive(Clone, Debug)]
 pub enum Repetition {
     Exactly(usize),
     Optional,
     Any,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::parse_error::ParseError;
+
+    #[test]
+    fn unknown_type() {
+        let Err(ParseError::UnexpectedToken {
+            found, expected, ..
+        }) = crate::parse("map f [x:foo] => 'f'", None)
+        else {
+            panic!("Expected an unknown type to be rejected");
+        };
+        assert_eq!(found, "Some(Ident(\"foo\"))");
+        assert!(expected.contains(&"Ident(\"int\")".to_string()));
+        assert!(expected.contains(&"Ident(\"lazy\")".to_string()));
+    }
+}
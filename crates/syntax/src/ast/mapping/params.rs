@@ -1,12 +1,50 @@
 use std::fmt;
 
 use super::param::Param;
+use crate::{
+    errors::parse_error::ParseResult,
+    lexer::ExprToken,
+    parser::{Parsable as _, Parser},
+    unexpected_eof,
+};
 
 #[derive(Clone)]
 pub struct Params {
     pub entries: Vec<Param>,
 }
 
+impl Params {
+    /// Parse params up to and including `end`
+    pub(crate) fn parse_until<'s>(
+        parser: &mut Parser<'s>,
+        end: ExprToken<'s>,
+    ) -> ParseResult<'s, Self> {
+        let mut entries = Vec::new();
+        loop {
+            match parser.current_expr()? {
+                Some(tok) if tok == end => break,
+                Some(_) => entries.push(Param::parse(parser)?),
+                None => unexpected_eof!(parser.ctx())?,
+            }
+        }
+        parser.skip(end, file!(), line!())?;
+        Ok(Self { entries })
+    }
+}
+
+/// In the syntax of a definition, separated by spaces
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, param) in self.entries.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{param}")?;
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for Params {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:#?}", self.entries)
@@ -28,6 +28,7 @@ macro_rules! derive_from {
     };
 }
 
+#[allow(clippy::module_inception)]
 mod ast;
 pub use ast::Ast;
 
@@ -49,4 +50,7 @@ pub use template_piece::TemplatePiece;
 pub mod mapping;
 
 mod block;
-pub use block::Block;
+pub use block::{Block, BlockFormat};
+
+mod is_expr;
+pub use is_expr::{IsBranch, IsExpr, IsPattern};
@@ -4,7 +4,7 @@ use crate::{
     errors::parse_error::ParseResult,
     lexer::ExprToken,
     parser::{Parsable, Parser},
-    unexpected_eof, unexpected_token,
+    unexpected_eof, unexpected_token, Symbol,
 };
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -16,14 +16,15 @@ pub enum PathIdentRoot {
 
 #[derive(Clone, PartialEq)]
 pub struct PathIdent {
-    pub original_src: String,
+    pub original_src: Symbol,
     pub root: PathIdentRoot,
-    pub path_parts: Vec<String>,
+    pub path_parts: Vec<Symbol>,
 }
 
 impl PathIdent {
-    pub fn name(&self) -> &str {
-        self.path_parts
+    pub fn name(&self) -> Symbol {
+        *self
+            .path_parts
             .last()
             .expect("Path ident needs at least one part")
     }
@@ -45,6 +46,7 @@ impl Parsable<'_> for PathIdent {
 }
 
 impl PathIdent {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(raw: &str) -> Self {
         // Determine Root and starting offset
         let (root, start_index) = if raw.starts_with("./") {
@@ -57,33 +59,29 @@ impl PathIdent {
 
         let main_path = &raw[start_index..];
 
-        let path_parts: Vec<String> = main_path.split('/').map(ToString::to_string).collect();
+        let path_parts: Vec<Symbol> = main_path.split('/').map(Symbol::intern).collect();
 
         PathIdent {
-            original_src: raw.to_string(),
+            original_src: Symbol::intern(raw),
             root,
             path_parts,
         }
     }
 
-    pub fn canonical(&self) -> String {
-        let prefix = match self.root {
-            PathIdentRoot::File => "",
-            PathIdentRoot::Directory => "./",
-            PathIdentRoot::Crate => "/",
-        };
-        format!("{}{}", prefix.to_string(), self.path_parts.join("/"))
+    /// The source is already canonical, it is split into the parts at every `/`
+    pub fn canonical(&self) -> Symbol {
+        self.original_src
     }
 }
 
 impl fmt::Debug for PathIdent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(&self.canonical())
+        f.write_str(self.canonical().as_str())
     }
 }
 
 impl fmt::Display for PathIdent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str(&self.canonical())
+        f.write_str(self.canonical().as_str())
     }
 }
@@ -3,6 +3,7 @@ use crate::{
     lexer::RawToken,
     parser::TokenizationMode,
     source_type::{Borrowed, SourceType},
+    unexpected_token,
 };
 
 use super::*;
@@ -12,9 +13,21 @@ pub struct TemplateString<S: SourceType> {
     pub pieces: Vec<TemplatePiece<S>>,
 }
 
+/// Intermediate parts of a template string, before dedenting and trimming
+enum Part<'s> {
+    Piece(TemplatePiece<Borrowed<'s>>),
+    /// Newline in the source, escaped newlines are pieces
+    Newline,
+    /// `[-` or `-]`, strips the whitespace next to an interpolation
+    Trim,
+}
+
 impl<'s> TemplateString<Borrowed<'s>> {
+    /// After a `dedent` directive, multi-line templates are dedented by the leading whitespace all
+    /// lines after the first have in common. A closing delimiter on its own line takes part, so
+    /// its indentation limits how much is stripped.
     pub fn parse(parser: &mut Parser<'s>, end_token: RawToken) -> Result<Self, ParseError> {
-        let mut pieces = Vec::new();
+        let mut parts = Vec::new();
 
         parser.switch_mode(TokenizationMode::Raw);
         parser.advance();
@@ -31,17 +44,17 @@ impl<'s> TemplateString<Borrowed<'s>> {
                     break;
                 }
                 RawToken::RawPart(s) => {
-                    pieces.push(TemplatePiece::StrVal(s));
+                    parts.push(Part::Piece(TemplatePiece::StrVal(s)));
                     parser.advance();
                 }
                 RawToken::Newline => {
-                    pieces.push(TemplatePiece::Char('\n'));
+                    parts.push(Part::Newline);
                     parser.advance();
                 }
                 RawToken::Escaped(ch) => {
                     match ch {
                         ch @ ('\n' | '\t' | '\\' | '\'' | '[' | ']' | '}' | '{') => {
-                            pieces.push(TemplatePiece::Char(ch))
+                            parts.push(Part::Piece(TemplatePiece::Char(ch)))
                         }
                         '\r' => {}
                         c => panic!("Unknown escape sequence: {c:?} in {:?}", parser.ctx()),
@@ -52,18 +65,40 @@ impl<'s> TemplateString<Borrowed<'s>> {
                     parser.switch_mode(TokenizationMode::Expr);
                     parser.advance();
                     assert_eq!(parser.current_expr(), Ok(Some(ExprToken::BlockStart)));
-                    pieces.push(TemplatePiece::Expr(Block::parse(parser)?.into()));
+                    parts.push(Part::Piece(TemplatePiece::Expr(
+                        Block::parse(parser)?.into(),
+                    )));
                 }
                 RawToken::ExprStart => {
                     parser.switch_mode(TokenizationMode::Expr);
                     parser.advance();
-                    pieces.push(TemplatePiece::Expr(Expr::parse(
-                        parser,
-                        TokenizationMode::Raw,
-                    )?));
+
+                    if parser.trim_marker_opens()? {
+                        parts.push(Part::Trim);
+                        parser.advance();
+                    }
+                    let was_interpolating = std::mem::replace(&mut parser.in_interpolation, true);
+                    let expr = Expr::parse(parser, TokenizationMode::Expr)?;
+                    parser.in_interpolation = was_interpolating;
+                    parts.push(Part::Piece(TemplatePiece::Expr(expr)));
+                    if parser.current_expr()? == Some(ExprToken::Symbol('-')) {
+                        parts.push(Part::Trim);
+                        parser.advance();
+                    }
+
+                    match parser.current_expr()? {
+                        Some(ExprToken::Symbol(']')) => {}
+                        tok => unexpected_token!(
+                            found: tok,
+                            expected: [Symbol(']'), Symbol('-')],
+                            @ parser.ctx()
+                        )?,
+                    }
+                    parser.switch_mode(TokenizationMode::Raw);
+                    parser.advance();
                 }
                 RawToken::TemplateStringDelimiter(_) => {
-                    pieces.push(TemplatePiece::StrVal(parser.slice()));
+                    parts.push(Part::Piece(TemplatePiece::StrVal(parser.slice())));
                     parser.advance();
                 }
                 RawToken::IgnoredLineContinuation => unreachable!(),
@@ -72,6 +107,202 @@ impl<'s> TemplateString<Borrowed<'s>> {
         parser.switch_mode(TokenizationMode::Expr);
         parser.advance();
 
-        Ok(Self { pieces })
+        if parser.dedent {
+            dedent(&mut parts);
+        }
+        Ok(Self {
+            pieces: trim(parts),
+        })
+    }
+}
+
+/// Leading whitespace of a line, `None` for lines with only whitespace
+fn indentation<'s>(line: &[Part<'s>]) -> Option<&'s str> {
+    let content = line.iter().find(|part| match part {
+        Part::Piece(TemplatePiece::StrVal(s)) => !s.trim().is_empty(),
+        Part::Piece(_) => true,
+        Part::Newline | Part::Trim => false,
+    })?;
+    match (line.first(), content) {
+        (Some(Part::Piece(TemplatePiece::StrVal(s))), _) => {
+            Some(&s[..s.len() - s.trim_start().len()])
+        }
+        _ => Some(""),
+    }
+}
+
+fn dedent(parts: &mut [Part<'_>]) {
+    let is_newline = |part: &Part| matches!(part, Part::Newline);
+
+    // Only templates opening with a line break are dedented, text right after the delimiter sets
+    // the indentation of the following lines itself
+    let lines: Vec<_> = parts.split(is_newline).collect();
+    if lines.len() < 2 || indentation(lines[0]).is_some() {
+        return;
+    }
+    // A closing delimiter on its own line counts as well, so that it can be used to keep
+    // some of the indentation
+    let closing = match lines[lines.len() - 1] {
+        [Part::Piece(TemplatePiece::StrVal(s))] if s.trim().is_empty() => Some(*s),
+        [] => Some(""),
+        _ => None,
+    };
+    let common = lines[1..]
+        .iter()
+        .filter_map(|line| indentation(line))
+        .chain(closing)
+        .reduce(|common, indent| {
+            let len = common
+                .char_indices()
+                .zip(indent.chars())
+                .take_while(|((_, a), b)| a == b)
+                .last()
+                .map_or(0, |((i, a), _)| i + a.len_utf8());
+            &common[..len]
+        });
+    let Some(common) = common.filter(|common| !common.is_empty()) else {
+        return;
+    };
+
+    for line in parts.split_mut(is_newline).skip(1) {
+        if let Some(Part::Piece(TemplatePiece::StrVal(s))) = line.first_mut() {
+            // Lines with less indentation only contain whitespace
+            let strip = s.len() - s.trim_start().len();
+            *s = &s[strip.min(common.len())..];
+        }
+    }
+}
+
+/// Strip whitespace next to `Trim` markers
+fn trim(parts: Vec<Part<'_>>) -> Vec<TemplatePiece<Borrowed<'_>>> {
+    let mut pieces: Vec<TemplatePiece<Borrowed>> = Vec::with_capacity(parts.len());
+    let mut trimming = false;
+
+    for part in parts {
+        match part {
+            Part::Trim if matches!(pieces.last(), Some(TemplatePiece::Expr(_))) => trimming = true,
+            Part::Trim => {
+                // Trim before the interpolation
+                while let Some(last) = pieces.pop() {
+                    match last {
+                        TemplatePiece::StrVal(s) if !s.trim_end().is_empty() => {
+                            pieces.push(TemplatePiece::StrVal(s.trim_end()));
+                            break;
+                        }
+                        TemplatePiece::StrVal(_) => {}
+                        TemplatePiece::Char(c) if c.is_whitespace() => {}
+                        last => {
+                            pieces.push(last);
+                            break;
+                        }
+                    }
+                }
+            }
+            Part::Newline if trimming => {}
+            Part::Newline => pieces.push(TemplatePiece::Char('\n')),
+            Part::Piece(piece) if trimming => match piece {
+                TemplatePiece::StrVal(s) if s.trim_start().is_empty() => {}
+                TemplatePiece::StrVal(s) => {
+                    pieces.push(TemplatePiece::StrVal(s.trim_start()));
+                    trimming = false;
+                }
+                TemplatePiece::Char(c) if c.is_whitespace() => {}
+                piece => {
+                    pieces.push(piece);
+                    trimming = false;
+                }
+            },
+            Part::Piece(piece) => pieces.push(piece),
+        }
+    }
+    pieces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse and render a template, interpolations are written as `{}`
+    fn render(src: &str) -> String {
+        let mut parser = Parser::new(src, None, None);
+        parser.dedent = true;
+        let tmpl = TemplateString::parse(
+            parser_at_delimiter(&mut parser),
+            RawToken::TemplateStringDelimiter(3),
+        )
+        .expect("Valid template");
+        tmpl.pieces
+            .iter()
+            .map(|piece| match piece {
+                TemplatePiece::StrVal(s) => s.to_string(),
+                TemplatePiece::Char(c) => c.to_string(),
+                TemplatePiece::Expr(_) => "{}".to_string(),
+            })
+            .collect()
+    }
+
+    fn parser_at_delimiter<'p, 's>(parser: &'p mut Parser<'s>) -> &'p mut Parser<'s> {
+        assert_eq!(
+            parser.current_expr(),
+            Ok(Some(ExprToken::TemplateStringDelimiter(3)))
+        );
+        parser
+    }
+
+    #[test]
+    fn dedent() {
+        assert_eq!(
+            render("'''\n    a\n      b\n\n    c\n    '''"),
+            "\na\n  b\n\nc\n"
+        );
+        assert_eq!(render("''' \n\t\t[x]\n\t\tb\n\t'''"), " \n\t{}\n\tb\n");
+        assert_eq!(render("'''\n    a\n'''"), "\n    a\n");
+        assert_eq!(render("'''\n    a\n      b\n  '''"), "\n  a\n    b\n");
+        assert_eq!(render("'''first\n    [x]\n'''"), "first\n    {}\n");
+        assert_eq!(render("'''\n  a\n\tb\n'''"), "\n  a\n\tb\n");
+    }
+
+    #[test]
+    fn trim_markers() {
+        assert_eq!(render("'''\n  [- x -]  \n  y'''"), "{}y");
+        assert_eq!(render("'''a [- x] b'''"), "a{} b");
+        assert_eq!(render("'''a [x -] \\n b'''"), "a {}b");
+        assert_eq!(render("'''[a -] [- b]'''"), "{}{}");
+    }
+
+    #[test]
+    fn minus_argument() {
+        let mut parser = Parser::new("'''|[foo -]|[foo '-']| [foo -] '''", None, None);
+        let tmpl = TemplateString::parse(
+            parser_at_delimiter(&mut parser),
+            RawToken::TemplateStringDelimiter(3),
+        )
+        .expect("Valid template");
+        let arg_counts: Vec<_> = tmpl
+            .pieces
+            .iter()
+            .filter_map(|piece| match piece {
+                TemplatePiece::Expr(Expr::MappingApplication(m_app)) => Some(m_app.args.len()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(arg_counts, [0, 1, 0]);
+    }
+
+    #[test]
+    fn leading_minus_operator() {
+        assert_eq!(render("'''a [-> b c] d [- e] f'''"), "a {} d{} f");
+
+        let mut parser = Parser::new("'''[-> a b]'''", None, None);
+        let tmpl = TemplateString::parse(
+            parser_at_delimiter(&mut parser),
+            RawToken::TemplateStringDelimiter(3),
+        )
+        .expect("Valid template");
+        let [TemplatePiece::Expr(Expr::MappingApplication(m_app))] = &tmpl.pieces[..] else {
+            panic!("Expected a single application, got {:?}", tmpl.pieces);
+        };
+        assert_eq!(m_app.name.to_string(), "->");
+        assert_eq!(m_app.args.len(), 2);
     }
 }
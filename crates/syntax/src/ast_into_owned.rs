@@ -1,9 +1,10 @@
 use crate::{
     ast::{
-        mapping::{Mapping, MappingApplication, ParameterizedMapping},
-        Block, Expr, TemplatePiece, TemplateString,
+        mapping::{Mapping, MappingApplication, NamedArg, ParameterizedMapping},
+        Block, BlockFormat, Expr, IsBranch, IsExpr, IsPattern, TemplatePiece, TemplateString,
     },
     source_type::{Owned, SourceType},
+    Overloads,
 };
 
 pub trait IntoOwned {
@@ -19,10 +20,13 @@ impl<S: SourceType> IntoOwned for Expr<S> {
             Expr::StrRef(s) => Expr::StrRef(s.to_string()),
             Expr::TemplateString(s) => Expr::TemplateString(s.into_owned()),
             Expr::Integer(i) => Expr::Integer(i),
+            Expr::SafeString(s) => Expr::SafeString(s),
             Expr::PathIdent(s) => Expr::PathIdent(s),
             Expr::LiteralSymbol(c) => Expr::LiteralSymbol(c),
+            Expr::LiteralOperator(op) => Expr::LiteralOperator(op),
             Expr::MappingApplication(ma) => Expr::MappingApplication(ma.into_owned()),
             Expr::Block(block) => Expr::Block(block.into_owned()),
+            Expr::Is(is_expr) => Expr::Is(is_expr.into_owned()),
         }
     }
 }
@@ -32,6 +36,20 @@ impl<S: SourceType> IntoOwned for Block<S> {
     fn into_owned(self) -> Block<Owned> {
         Block {
             exprs: self.exprs.into_iter().map(IntoOwned::into_owned).collect(),
+            format: self.format.into_owned(),
+        }
+    }
+}
+
+impl<S: SourceType> IntoOwned for BlockFormat<S> {
+    type Owned = BlockFormat<Owned>;
+    fn into_owned(self) -> BlockFormat<Owned> {
+        let into_owned = |expr: Option<Box<Expr<S>>>| expr.map(|expr| Box::new(expr.into_owned()));
+        BlockFormat {
+            separator: into_owned(self.separator),
+            prefix: into_owned(self.prefix),
+            suffix: into_owned(self.suffix),
+            trailing: self.trailing,
         }
     }
 }
@@ -68,6 +86,23 @@ impl<S: SourceType> IntoOwned for Mapping<S> {
     }
 }
 
+impl<S: SourceType> IntoOwned for Overloads<S> {
+    type Owned = Overloads<Owned>;
+    fn into_owned(self) -> Overloads<Owned> {
+        // The index doesn't depend on the source type
+        Overloads {
+            mappings: self
+                .mappings
+                .into_iter()
+                .map(IntoOwned::into_owned)
+                .collect(),
+            origins: self.origins,
+            index: self.index,
+            flexible: self.flexible,
+        }
+    }
+}
+
 impl<S: SourceType> IntoOwned for ParameterizedMapping<S> {
     type Owned = ParameterizedMapping<Owned>;
     fn into_owned(self) -> ParameterizedMapping<Owned> {
@@ -84,6 +119,70 @@ impl<S: SourceType> IntoOwned for MappingApplication<S> {
         MappingApplication {
             name: self.name,
             args: self.args.into_iter().map(IntoOwned::into_owned).collect(),
+            named: self.named.into_iter().map(IntoOwned::into_owned).collect(),
+        }
+    }
+}
+
+impl<S: SourceType> IntoOwned for NamedArg<S> {
+    type Owned = NamedArg<Owned>;
+    fn into_owned(self) -> NamedArg<Owned> {
+        NamedArg {
+            name: self.name,
+            value: self.value.into_owned(),
+        }
+    }
+}
+
+impl<S: SourceType> IntoOwned for IsExpr<S> {
+    type Owned = IsExpr<Owned>;
+    fn into_owned(self) -> IsExpr<Owned> {
+        IsExpr {
+            expr: Box::new(self.expr.into_owned()),
+            branches: self
+                .branches
+                .into_iter()
+                .map(IntoOwned::into_owned)
+                .collect(),
+        }
+    }
+}
+
+impl<S: SourceType> IntoOwned for IsBranch<S> {
+    type Owned = IsBranch<Owned>;
+    fn into_owned(self) -> IsBranch<Owned> {
+        IsBranch {
+            alternatives: self
+                .alternatives
+                .into_iter()
+                .map(IntoOwned::into_owned)
+                .collect(),
+            guard: self.guard.map(IntoOwned::into_owned),
+            translation: self.translation.into_owned(),
+        }
+    }
+}
+
+impl<S: SourceType> IntoOwned for IsPattern<S> {
+    type Owned = IsPattern<Owned>;
+    fn into_owned(self) -> IsPattern<Owned> {
+        match self {
+            IsPattern::Wildcard => IsPattern::Wildcard,
+            IsPattern::Value(expr) => IsPattern::Value(expr.into_owned()),
+            IsPattern::Range {
+                start,
+                end,
+                inclusive,
+            } => IsPattern::Range {
+                start: start.into_owned(),
+                end: end.map(IntoOwned::into_owned),
+                inclusive,
+            },
+            IsPattern::Glob(glob) => IsPattern::Glob(glob.into_owned()),
+            IsPattern::Binding { name, pattern } => IsPattern::Binding {
+                name,
+                pattern: Box::new(pattern.into_owned()),
+            },
         }
     }
 }
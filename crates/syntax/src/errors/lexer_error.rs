@@ -20,7 +20,7 @@ pub enum LexerError {
 impl LexerError {
     pub fn ctx(&self) -> &FileContext {
         match self {
-            Self::UnknownError { ctx, .. } => &ctx,
+            Self::UnknownError { ctx, .. } => ctx,
         }
     }
 }
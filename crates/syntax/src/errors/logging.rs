@@ -32,10 +32,14 @@ pub fn _log_ex(
     }
 }
 
+/// Only logs in debug builds, formatting the arguments for every expansion step dominates the run
+/// time otherwise
 #[macro_export]
 macro_rules! log {
     ($($args:tt)*) => {
-        $crate::errors::logging::_log_ex($crate::errors::logging::LOG_FILE_PATH, file!(), line!(), format_args!($($args)*));
+        if cfg!(debug_assertions) {
+            $crate::errors::logging::_log_ex($crate::errors::logging::LOG_FILE_PATH, file!(), line!(), format_args!($($args)*));
+        }
     };
 }
 
@@ -25,6 +25,12 @@ pub enum ParseError {
         file: &'static str,
         line: u32,
     },
+    InvalidRegex {
+        error: String,
+        ctx: Box<FileContext>,
+        file: &'static str,
+        line: u32,
+    },
 }
 
 derive_from!(LexerError for ParseError);
@@ -33,8 +39,9 @@ impl ParseError {
     pub fn ctx(&self) -> &FileContext {
         match self {
             Self::LexerError(lexer_err) => lexer_err.ctx(),
-            Self::UnexpectedToken { ctx, .. } => &ctx,
-            Self::UnexpectedEof { ctx, .. } => &ctx,
+            Self::UnexpectedToken { ctx, .. } => ctx,
+            Self::UnexpectedEof { ctx, .. } => ctx,
+            Self::InvalidRegex { ctx, .. } => ctx,
         }
     }
 }
@@ -61,6 +68,16 @@ impl fmt::Display for ParseError {
                 print_err_ctx(f, ctx)?;
                 writeln!(f, "|  Unexpected end of file")
             }
+            ParseError::InvalidRegex {
+                error,
+                ctx,
+                file,
+                line,
+            } => {
+                print_raise_ctx(f, file, *line)?;
+                print_err_ctx(f, ctx)?;
+                writeln!(f, "|  Invalid regex: {error}")
+            }
             ParseError::LexerError(err) => write!(f, "{err}"),
         }
     }
@@ -50,7 +50,7 @@ mod tests {
 
     #[test]
     fn idents() {
-        const INPUT: &'static str = " ident i_d3n_t _D _ _1 42-1 a-a _a-8--D";
+        const INPUT: &str = " ident i_d3n_t _D _ _1 42-1 a-a _a-8--D";
         let mut lexer = ExprToken::lexer_with_extras(INPUT, TrackingContext::default());
         assert_eq!(lexer.next(), Some(Ok(ExprToken::Ident("ident"))));
         assert_eq!(lexer.next(), Some(Ok(ExprToken::Ident("i_d3n_t"))));
@@ -10,7 +10,10 @@ mod lexer;
 pub use lexer::FileContext;
 
 mod program_context;
-pub use program_context::ProgramContext;
+pub use program_context::{Origin, Overloads, ProgramContext};
+
+mod symbol;
+pub use symbol::Symbol;
 
 use crate::{
     ast::Ast,
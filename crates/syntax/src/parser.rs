@@ -8,9 +8,12 @@ use crate::{
         parse_error::ParseResult,
     },
     lexer::{ExprToken, FileContext, RawToken, Token, TrackingContext},
-    log_lexer,
+    log_lexer, unexpected_eof,
 };
 
+/// Characters that form multi-character operators (`->`, `::`) when written without space
+pub(crate) const OPERATOR_CHARS: &str = "+-*/%&^~<>=!:?@#$";
+
 pub type LogosError<'s> = <ExprToken<'s> as Logos<'s>>::Error;
 pub type ExprLexer<'s> = logos::Lexer<'s, ExprToken<'s>>;
 pub type RawLexer<'s> = logos::Lexer<'s, RawToken<'s>>;
@@ -31,6 +34,12 @@ pub enum TokenizationMode {
 pub struct Parser<'s> {
     pub(crate) mode: TokenizationMode,
     pub(crate) ignoring_newlines: bool,
+    /// Whether the parser is directly inside of a template interpolation, where `-]` can be a
+    /// trim marker
+    pub(crate) in_interpolation: bool,
+    /// Whether multi-line templates are dedented, set by a `dedent` directive for the templates
+    /// that follow it
+    pub(crate) dedent: bool,
 
     expr_lexer: ExprLexer<'s>,
     raw_lexer: RawLexer<'s>,
@@ -49,6 +58,8 @@ impl<'s> Parser<'s> {
             source_name,
             mode: TokenizationMode::Expr,
             ignoring_newlines: true,
+            in_interpolation: false,
+            dedent: false,
             expr_lexer,
             raw_lexer,
             current_expr: None,
@@ -81,7 +92,15 @@ impl<'s> Parser<'s> {
     /// Skip newlines while the current expr token is a newline
     pub fn skip_newlines(&mut self) {
         while let Some(Ok(ExprToken::Newline)) = self.current_expr {
-            self.current_expr = self.expr_lexer.next();
+            self.next_expr_token();
+        }
+    }
+
+    /// Fetch the next expr token and keep track of the column. Newlines already reset the column
+    /// in the lexer
+    fn next_expr_token(&mut self) {
+        self.current_expr = self.expr_lexer.next();
+        if !matches!(self.current_expr, Some(Ok(ExprToken::Newline))) {
             self.expr_lexer.extras.column += self.expr_lexer.slice().len();
         }
     }
@@ -89,21 +108,17 @@ impl<'s> Parser<'s> {
     pub fn advance(&mut self) {
         match self.mode {
             TokenizationMode::Expr => {
-                self.current_expr = self.expr_lexer.next();
-                self.expr_lexer.extras.column += self.expr_lexer.slice().len();
+                self.next_expr_token();
                 if self.ignoring_newlines {
-                    while let Some(Ok(ExprToken::Newline)) = self.current_expr {
-                        self.current_expr = self.expr_lexer.next();
-                        self.expr_lexer.extras.column += self.expr_lexer.slice().len();
-                    }
+                    self.skip_newlines();
                 }
-                if let Some(_) = self.log_file {
+                if self.log_file.is_some() {
                     log_lexer!(file, "Expr: {:?}", self.current_expr);
                 }
             }
             TokenizationMode::Raw => {
                 self.current_raw = self.raw_lexer.next();
-                if let Some(_) = self.log_file {
+                if self.log_file.is_some() {
                     log_lexer!(file, "Raw: {:?}", self.current_raw);
                 }
             }
@@ -192,6 +207,107 @@ impl<'s> Parser<'s> {
         }
     }
 
+    /// Consume the current symbol, together with the operator characters directly following it
+    /// if it is one itself. Returns the source of the symbol or operator, like `+` or `->`.
+    pub(crate) fn operator(&mut self) -> ParseResult<'s, &'s str> {
+        let Some(ExprToken::Symbol(first)) = self.current_expr()? else {
+            panic!("Parser::operator on no symbol");
+        };
+        let span = self.expr_lexer.span();
+        let (start, mut end) = (span.start, span.end);
+        self.advance();
+        if !OPERATOR_CHARS.contains(first) {
+            return Ok(&self.expr_lexer.source()[start..end]);
+        }
+
+        while let Some(ExprToken::Symbol(c)) = self.current_expr()? {
+            if self.expr_lexer.span().start != end || !OPERATOR_CHARS.contains(c) {
+                break;
+            }
+            if c == '-' && self.trim_marker_follows()? {
+                break;
+            }
+            end = self.expr_lexer.span().end;
+            self.advance();
+        }
+        Ok(&self.expr_lexer.source()[start..end])
+    }
+
+    /// Whether the current `-` is the trim marker of a template interpolation (`-]`). A minus as
+    /// last argument of an interpolation has to be quoted, `[foo '-']`.
+    pub(crate) fn trim_marker_follows(&self) -> ParseResult<'s, bool> {
+        if !self.in_interpolation {
+            return Ok(false);
+        }
+        let mut lookahead = self.clone();
+        lookahead.advance();
+        Ok(lookahead.current_expr()? == Some(ExprToken::Symbol(']')))
+    }
+
+    /// Whether the current token is the trim marker opening a template interpolation (`[- x]`).
+    /// It has to be followed by whitespace, `[-> a b]` applies the operator `->`.
+    pub(crate) fn trim_marker_opens(&self) -> ParseResult<'s, bool> {
+        if self.current_expr()? != Some(ExprToken::Symbol('-')) {
+            return Ok(false);
+        }
+        let rest = &self.expr_lexer.source()[self.expr_lexer.span().end..];
+        Ok(rest.starts_with(char::is_whitespace))
+    }
+
+    /// Consume the source from the current token, which has to start with `delimiter`, up to the
+    /// next unescaped `delimiter` on the same line, like `/[a-d]x/`. Returns the source in
+    /// between.
+    pub(crate) fn delimited(&mut self, delimiter: char) -> ParseResult<'s, &'s str> {
+        let src = self.expr_lexer.source();
+        let span = self.expr_lexer.span();
+        let start = span.start + delimiter.len_utf8();
+
+        let mut escaped = false;
+        let mut end = None;
+        for (i, c) in src[start..].char_indices() {
+            match c {
+                '\n' => break,
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                c if c == delimiter => {
+                    end = Some(start + i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let Some(end) = end else {
+            return unexpected_eof!(self.ctx());
+        };
+
+        // The current token might end before or after the closing delimiter, continue lexing
+        // right after it
+        let after = end + delimiter.len_utf8();
+        let mut extras = self.expr_lexer.extras;
+        extras.column = extras.column + after - span.end;
+        let mut lexer = ExprToken::lexer_with_extras(src, extras);
+        lexer.bump(after);
+        self.expr_lexer = lexer;
+        self.advance();
+
+        Ok(&src[start..end])
+    }
+
+    /// Source between the current token and `start`
+    pub(crate) fn source_since(&self, start: usize) -> &'s str {
+        &self.expr_lexer.source()[start..self.expr_lexer.span().start]
+    }
+
+    /// The source after the current token
+    pub(crate) fn rest(&self) -> &'s str {
+        &self.expr_lexer.source()[self.expr_lexer.span().end..]
+    }
+
+    /// Where the current token starts in the source
+    pub(crate) fn position(&self) -> usize {
+        self.expr_lexer.span().start
+    }
+
     pub fn skip<T>(&mut self, token: T, file: &'static str, line: u32) -> ParseResult<'s, ()>
     where
         T: Into<Token<'s>> + Copy,
@@ -204,8 +320,8 @@ impl<'s> Parser<'s> {
                     found,
                     expected,
                     ctx: self.ctx(),
-                    file: file,
-                    line: line,
+                    file,
+                    line,
                 })
             };
         }
@@ -456,4 +572,16 @@ map bar [param] => '''nested [tmplstr 'str']'''
         p.advance();
         assert_eq!(p.current(), Ok(None));
     }
+
+    #[test]
+    fn operators() {
+        let mut p = Parser::new("-> : : ::= -]", None, None);
+        assert_eq!(p.operator().unwrap(), "->");
+        assert_eq!(p.operator().unwrap(), ":");
+        assert_eq!(p.operator().unwrap(), ":");
+        assert_eq!(p.operator().unwrap(), "::=");
+        // Trim marker
+        assert_eq!(p.operator().unwrap(), "-");
+        assert_eq!(p.current_expr(), Ok(Some(ExprToken::Symbol(']'))));
+    }
 }
@@ -1,5 +1,279 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt};
 
-use crate::{ast::mapping::Mapping, source_type::SourceType};
+use crate::{
+    ast::{
+        mapping::{Mapping, MappingApplication, Param, ParameterizedMapping},
+        Expr,
+    },
+    source_type::SourceType,
+    Symbol,
+};
 
-pub type ProgramContext<S> = HashMap<<S as SourceType>::Str, Vec<Mapping<S>>>;
+pub type ProgramContext<S> = HashMap<Symbol, Overloads<S>>;
+
+/// All mappings defined for a name, indexed for dispatch
+///
+/// The index is a decision tree that branches on the number of arguments first, then on the
+/// literal parameters (`map tag div [body] => ..`) position by position. Looking up the
+/// candidates for some arguments only visits the branches their literals and the captures lead
+/// to, so it doesn't get slower with the number of overloads for other literals. Overloads with
+/// default values take different numbers of arguments and are always candidates.
+#[derive(Clone)]
+pub struct Overloads<S: SourceType> {
+    pub(crate) mappings: Vec<Mapping<S>>,
+    /// Where each of `mappings` was defined
+    pub(crate) origins: Vec<Origin>,
+    pub(crate) index: HashMap<usize, DispatchNode>,
+    /// Overloads with optional parameters, not in `index`
+    pub(crate) flexible: Vec<usize>,
+}
+
+/// Where a mapping was defined, the position of its `map` keyword
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Origin {
+    pub source_name: Option<Symbol>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.source_name {
+            Some(source_name) => write!(f, "{source_name}:{}:{}", self.line, self.column),
+            None => write!(f, "{}:{}", self.line, self.column),
+        }
+    }
+}
+
+/// Node of the dispatch tree, for the parameters up to some position
+#[derive(Clone, Default)]
+pub(crate) struct DispatchNode {
+    /// Overloads without further parameters, as indices into `Overloads::mappings`
+    leaves: Vec<usize>,
+    /// Overloads with a literal parameter at this position
+    literals: HashMap<LiteralKey, DispatchNode>,
+    /// Overloads with a capture at this position
+    capture: Option<Box<DispatchNode>>,
+}
+
+/// What a literal parameter requires of the argument at its position
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum LiteralKey {
+    /// An ident, or an application of that name
+    Name(Symbol),
+    Symbol(char),
+    Operator(Symbol),
+    Integer(i64),
+    /// Any string, string literals aren't told apart by the index
+    String,
+    Block,
+}
+
+impl LiteralKey {
+    /// `None` if the parameter captures the argument
+    fn of_param(param: &Param) -> Option<Self> {
+        match param {
+            Param::Ident(ident) | Param::Application { name: ident, .. } => {
+                Some(Self::Name(ident.canonical()))
+            }
+            Param::Symbol(c) => Some(Self::Symbol(*c)),
+            Param::Operator(op) => Some(Self::Operator(*op)),
+            Param::Integer(n) => Some(Self::Integer(*n)),
+            Param::String(_) => Some(Self::String),
+            Param::Block(_) => Some(Self::Block),
+            Param::ParamExpr { .. } => None,
+        }
+    }
+
+    /// The only key of literal parameters the argument can match syntactically, `None` if there
+    /// is none
+    fn of_arg<S: SourceType>(arg: &Expr<S>) -> Option<Self> {
+        match arg {
+            Expr::PathIdent(ident)
+            | Expr::MappingApplication(MappingApplication { name: ident, .. }) => {
+                Some(Self::Name(ident.canonical()))
+            }
+            Expr::LiteralSymbol(c) => Some(Self::Symbol(*c)),
+            Expr::LiteralOperator(op) => Some(Self::Operator(*op)),
+            Expr::Integer(n) => Some(Self::Integer(*n)),
+            Expr::String(_) | Expr::StrRef(_) | Expr::SafeString(_) => Some(Self::String),
+            Expr::Block(_) => Some(Self::Block),
+            Expr::TemplateString(_) | Expr::Is(_) => None,
+        }
+    }
+
+    /// Whether the argument can also match literal values by what it expands to
+    fn is_computed<S: SourceType>(arg: &Expr<S>) -> bool {
+        matches!(
+            arg,
+            Expr::PathIdent(_)
+                | Expr::MappingApplication(_)
+                | Expr::TemplateString(_)
+                | Expr::Is(_)
+        )
+    }
+
+    fn is_value(self) -> bool {
+        matches!(self, Self::Integer(_) | Self::String)
+    }
+}
+
+impl<S: SourceType> Default for Overloads<S> {
+    fn default() -> Self {
+        Self {
+            mappings: Vec::new(),
+            origins: Vec::new(),
+            index: HashMap::new(),
+            flexible: Vec::new(),
+        }
+    }
+}
+
+impl<S: SourceType> Overloads<S> {
+    pub fn push(&mut self, mapping: Mapping<S>, origin: Origin) {
+        let params: &[Param] = match &mapping {
+            Mapping::SimpleMapping(_) => &[],
+            Mapping::ParameterizedMapping(ParameterizedMapping { params, .. }) => &params.entries,
+        };
+
+        if params.iter().any(Param::is_optional) {
+            self.flexible.push(self.mappings.len());
+        } else {
+            let mut node = self.index.entry(params.len()).or_default();
+            for param in params {
+                node = match LiteralKey::of_param(param) {
+                    Some(key) => node.literals.entry(key).or_default(),
+                    None => node.capture.get_or_insert_default(),
+                };
+            }
+            node.leaves.push(self.mappings.len());
+        }
+
+        self.mappings.push(mapping);
+        self.origins.push(origin);
+    }
+
+    /// Add the overloads of `other`, except for the ones that were defined at the same place as
+    /// one of these. Files imported through several others are merged more than once.
+    pub fn append(&mut self, other: Overloads<S>) {
+        for (mapping, origin) in other.mappings.into_iter().zip(other.origins) {
+            if !self.origins.contains(&origin) {
+                self.push(mapping, origin);
+            }
+        }
+    }
+
+    /// Overloads that might match `args`, in the order they were defined. Whether they do match
+    /// still has to be checked. Calls with named arguments can't be dispatched by position, so all
+    /// overloads are candidates for them.
+    pub fn candidates<'a, T: SourceType>(
+        &'a self,
+        args: &[Expr<T>],
+        named: bool,
+    ) -> impl Iterator<Item = (&'a Mapping<S>, Origin)> {
+        let mut indices = Vec::new();
+        if named {
+            indices.extend(0..self.mappings.len());
+        } else {
+            if let Some(node) = self.index.get(&args.len()) {
+                node.collect(args, &mut indices);
+            }
+            indices.extend(&self.flexible);
+            indices.sort_unstable();
+        }
+        indices
+            .into_iter()
+            .map(|i| (&self.mappings[i], self.origins[i]))
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Mapping<S>> {
+        self.mappings.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.mappings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+}
+
+impl DispatchNode {
+    fn collect<S: SourceType>(&self, args: &[Expr<S>], indices: &mut Vec<usize>) {
+        let Some((arg, rest)) = args.split_first() else {
+            indices.extend(&self.leaves);
+            return;
+        };
+        let key = LiteralKey::of_arg(arg);
+        if let Some(node) = key.and_then(|key| self.literals.get(&key)) {
+            node.collect(rest, indices);
+        }
+        if LiteralKey::is_computed(arg) {
+            for (_, node) in self
+                .literals
+                .iter()
+                .filter(|(literal, _)| literal.is_value() && Some(**literal) != key)
+            {
+                node.collect(rest, indices);
+            }
+        }
+        if let Some(node) = &self.capture {
+            node.collect(rest, indices);
+        }
+    }
+}
+
+impl<'a, S: SourceType> IntoIterator for &'a Overloads<S> {
+    type Item = &'a Mapping<S>;
+    type IntoIter = std::slice::Iter<'a, Mapping<S>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<S: SourceType> fmt::Debug for Overloads<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.mappings).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::{Expr, PathIdent},
+        source_type::Owned,
+        Symbol,
+    };
+
+    #[test]
+    fn candidates() {
+        let ast = crate::parse(
+            "map tag div [body] => 'div'\n\
+             map tag [name] [body] => 'any'\n\
+             map tag span [body] => 'span'\n\
+             map tag [name] => 'empty'\n\
+             map tag => 'none'\n\
+             map tag [name] [class=\"\"] [body] => 'class'\n",
+            None,
+        )
+        .unwrap();
+        let overloads = &ast.ctx[&Symbol::intern("tag")];
+
+        let ident = |name| Expr::<Owned>::PathIdent(PathIdent::from_str(name));
+        let indices = |args: &[Expr<Owned>]| {
+            overloads
+                .candidates(args, false)
+                .map(|(m, _)| overloads.iter().position(|o| std::ptr::eq(o, m)).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(indices(&[ident("div"), ident("x")]), [0, 1, 5]);
+        assert_eq!(indices(&[ident("span"), ident("x")]), [1, 2, 5]);
+        assert_eq!(indices(&[Expr::Integer(1), ident("x")]), [1, 5]);
+        assert_eq!(indices(&[ident("div")]), [3, 5]);
+        assert_eq!(indices(&[]), [4, 5]);
+        assert_eq!(indices(&[ident("a"), ident("b"), ident("c")]), [5]);
+    }
+}
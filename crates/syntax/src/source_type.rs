@@ -1,6 +1,6 @@
-use std::{fmt::Debug, marker::PhantomData};
+use std::{borrow::Cow, fmt::Debug, marker::PhantomData};
 
-use crate::IntoOwned;
+use crate::{ast::Expr, IntoOwned};
 
 /// How to represent the source
 pub trait SourceType: Clone + Debug + IntoOwned {
@@ -11,6 +11,9 @@ pub trait SourceType: Clone + Debug + IntoOwned {
         + ToString
         + AsRef<str>
         + std::borrow::Borrow<str>;
+
+    /// View expressions as owned ones, only converting them if they borrow the source
+    fn owned_exprs(exprs: &[Expr<Self>]) -> Cow<'_, [Expr<Owned>]>;
 }
 
 /// Owned version of SourceType
@@ -22,6 +25,10 @@ pub struct Owned;
 
 impl SourceType for Owned {
     type Str = String;
+
+    fn owned_exprs(exprs: &[Expr<Self>]) -> Cow<'_, [Expr<Owned>]> {
+        Cow::Borrowed(exprs)
+    }
 }
 
 impl IntoOwned for Owned {
@@ -40,6 +47,10 @@ pub struct Borrowed<'s>(
 
 impl<'s> SourceType for Borrowed<'s> {
     type Str = &'s str;
+
+    fn owned_exprs(exprs: &[Expr<Self>]) -> Cow<'_, [Expr<Owned>]> {
+        Cow::Owned(exprs.iter().cloned().map(IntoOwned::into_owned).collect())
+    }
 }
 
 impl IntoOwned for Borrowed<'_> {
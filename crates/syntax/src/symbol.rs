@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{LazyLock, PoisonError, RwLock},
+};
+
+/// Interned string, used for mapping names and identifiers
+///
+/// Symbols are compared and hashed as integers. The interner is global, so symbols can be passed
+/// between crates and files freely. Interned strings are never freed, which is fine for the names
+/// in a program.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+static INTERNER: LazyLock<RwLock<Interner>> = LazyLock::new(Default::default);
+
+impl Symbol {
+    pub fn intern(s: &str) -> Self {
+        if let Some(&symbol) = INTERNER
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .symbols
+            .get(s)
+        {
+            return symbol;
+        }
+
+        let mut interner = INTERNER.write().unwrap_or_else(PoisonError::into_inner);
+        // Another thread might have interned it in between
+        if let Some(&symbol) = interner.symbols.get(s) {
+            return symbol;
+        }
+        let s: &'static str = Box::leak(s.into());
+        let symbol = Symbol(interner.strings.len().try_into().expect("Too many symbols"));
+        interner.strings.push(s);
+        interner.symbols.insert(s, symbol);
+        symbol
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .strings[self.0 as usize]
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(s: &str) -> Self {
+        Self::intern(s)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Symbol;
+
+    #[test]
+    fn intern() {
+        let a = Symbol::intern("str/snake");
+        assert_eq!(a, Symbol::intern(&String::from("str/snake")));
+        assert_ne!(a, Symbol::intern("str/camel"));
+        assert_eq!(a.as_str(), "str/snake");
+        assert_eq!(Symbol::intern("").as_str(), "");
+    }
+}